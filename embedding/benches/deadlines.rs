@@ -0,0 +1,113 @@
+//! Times registering, re-polling and waking many deadlines on one scheduler,
+//! as when many instances share an executor, next to the same work on the
+//! unsorted `Vec` queue the executor used to keep. Run with `cargo bench`.
+
+use embedding::Scheduler;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, RawWaker, RawWakerVTable, Waker};
+use std::time::Instant;
+
+fn noop_waker() -> Waker {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(
+        |_| RawWaker::new(std::ptr::null(), &VTABLE),
+        |_| {},
+        |_| {},
+        |_| {},
+    );
+    // SAFETY: the vtable functions do nothing with the data pointer.
+    unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+}
+
+/// The executor's old deadline queue: every poll pushes another entry,
+/// finding the earliest scans them all, and waking removes them one by one.
+#[derive(Default)]
+struct VecDeadlines(Vec<(u64, Waker)>);
+
+impl VecDeadlines {
+    fn push(&mut self, deadline: u64, waker: Waker) {
+        self.0.push((deadline, waker))
+    }
+    fn earliest(&self) -> Option<u64> {
+        self.0.iter().map(|(d, _)| d).min().copied()
+    }
+    fn ready(&mut self, now: u64) -> Vec<Waker> {
+        let mut i = 0;
+        let mut wakers = Vec::new();
+        while i < self.0.len() {
+            if self.0[i].0 <= now {
+                wakers.push(self.0.remove(i).1);
+            } else {
+                i += 1;
+            }
+        }
+        wakers
+    }
+}
+
+// The old queue is quadratic, so it is only timed up to this many deadlines.
+const VEC_LIMIT: u64 = 10_000;
+
+fn main() {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    for n in [1_000u64, 10_000, 50_000] {
+        let scheduler = Scheduler::new();
+        let timer = scheduler.timer();
+
+        let start = Instant::now();
+        // Spread the deadlines out of order, as independent timers would be.
+        let mut deadlines = (0..n)
+            .map(|i| timer.at(1 + (i * 7919) % n))
+            .collect::<Vec<_>>();
+        // Guests re-poll their pollables each time they wake, re-registering
+        // the same deadline.
+        for _ in 0..4 {
+            for deadline in &mut deadlines {
+                assert!(Pin::new(deadline).poll(&mut cx).is_pending());
+            }
+        }
+        let registered = start.elapsed();
+
+        let start = Instant::now();
+        let mut advances = 0;
+        while let Some(due) = scheduler.earliest_deadline() {
+            scheduler.advance_clock(due);
+            advances += 1;
+        }
+        let drained = start.elapsed();
+
+        println!(
+            "{n:>6} deadlines: register and re-poll {registered:?}, \
+             advance through {advances} deadlines {drained:?}"
+        );
+
+        if n > VEC_LIMIT {
+            println!("{n:>6} deadlines, old vec queue: skipped");
+            continue;
+        }
+        let start = Instant::now();
+        let mut queue = VecDeadlines::default();
+        for _ in 0..4 {
+            for i in 0..n {
+                queue.push(1 + (i * 7919) % n, waker.clone());
+            }
+        }
+        let registered = start.elapsed();
+
+        let start = Instant::now();
+        let mut advances = 0;
+        while let Some(due) = queue.earliest() {
+            for waker in queue.ready(due) {
+                waker.wake();
+            }
+            advances += 1;
+        }
+        let drained = start.elapsed();
+
+        println!(
+            "{n:>6} deadlines, old vec queue: register and re-poll {registered:?}, \
+             advance through {advances} deadlines {drained:?}"
+        );
+    }
+}
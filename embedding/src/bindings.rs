@@ -15,6 +15,8 @@ wasmtime::component::bindgen!({
     trappable_imports: true,
     with: {
         "wasi:io": wasmtime_wasi_io::bindings::wasi::io,
+        "wasi:filesystem/types/descriptor": filesystem::Descriptor,
+        "wasi:filesystem/types/directory-entry-stream": filesystem::DirectoryEntryStream,
         "wasi:http/types/incoming-request": http::IncomingRequestResource,
         "wasi:http/types/outgoing-response": http::OutgoingResponseResource,
         "wasi:http/types/outgoing-request": http::OutgoingRequestResource,
@@ -29,6 +31,25 @@ wasmtime::component::bindgen!({
     }
 });
 
+// Commands only need the wasi:cli imports, all of which are implemented against the bindings
+// above.
+mod command {
+    wasmtime::component::bindgen!({
+        world: "wasi:cli/command",
+        async: { only_imports: [] },
+        trappable_imports: true,
+        with: {
+            "wasi:io": wasmtime_wasi_io::bindings::wasi::io,
+            "wasi:cli": crate::bindings::wasi::cli,
+            "wasi:clocks": crate::bindings::wasi::clocks,
+            "wasi:filesystem": crate::bindings::wasi::filesystem,
+            "wasi:random": crate::bindings::wasi::random,
+            "wasi:sockets": crate::bindings::wasi::sockets,
+        }
+    });
+}
+pub use command::CommandPre;
+
 pub fn add_to_linker_async(linker: &mut Linker<EmbeddingCtx>) -> Result<()> {
     fn type_annotate<F>(val: F) -> F
     where
@@ -39,6 +60,7 @@ pub fn add_to_linker_async(linker: &mut Linker<EmbeddingCtx>) -> Result<()> {
 
     let closure = type_annotate(|t| t);
     wasi::clocks::monotonic_clock::add_to_linker_get_host(linker, closure)?;
+    wasi::clocks::wall_clock::add_to_linker_get_host(linker, closure)?;
     wasi::cli::environment::add_to_linker_get_host(linker, closure)?;
     wasi::cli::exit::add_to_linker_get_host(linker, closure)?;
     wasi::cli::stdin::add_to_linker_get_host(linker, closure)?;
@@ -47,6 +69,8 @@ pub fn add_to_linker_async(linker: &mut Linker<EmbeddingCtx>) -> Result<()> {
     wasi::filesystem::preopens::add_to_linker_get_host(linker, closure)?;
     wasi::filesystem::types::add_to_linker_get_host(linker, closure)?;
     wasi::random::random::add_to_linker_get_host(linker, closure)?;
+    wasi::random::insecure::add_to_linker_get_host(linker, closure)?;
+    wasi::random::insecure_seed::add_to_linker_get_host(linker, closure)?;
     wasi::http::types::add_to_linker_get_host(linker, closure)?;
     wasi::http::outgoing_handler::add_to_linker_get_host(linker, closure)?;
     Ok(())
@@ -1,8 +1,9 @@
 use crate::ctx::EmbeddingCtx;
+use crate::ExitStatus;
 use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec::Vec;
-use anyhow::{bail, Result};
+use anyhow::Result;
 use wasmtime::component::Resource;
 use wasmtime_wasi_io::{
     streams::{DynInputStream, DynOutputStream},
@@ -13,23 +14,19 @@ use super::wasi::cli::{environment, exit, stderr, stdin, stdout};
 
 impl environment::Host for EmbeddingCtx {
     fn get_arguments(&mut self) -> Result<Vec<String>> {
-        Ok(Vec::new())
+        Ok(self.arguments().to_vec())
     }
     fn get_environment(&mut self) -> Result<Vec<(String, String)>> {
-        Ok(Vec::new())
+        Ok(self.environment().to_vec())
     }
     fn initial_cwd(&mut self) -> Result<Option<String>> {
-        Ok(None)
+        Ok(self.initial_cwd().map(String::from))
     }
 }
 
 impl exit::Host for EmbeddingCtx {
     fn exit(&mut self, code: Result<(), ()>) -> Result<()> {
-        if code.is_ok() {
-            bail!("wasi exit success")
-        } else {
-            bail!("wasi exit error")
-        }
+        Err(ExitStatus::from(code).into())
     }
 }
 
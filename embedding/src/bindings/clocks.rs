@@ -6,7 +6,7 @@ use wasmtime_wasi_io::{
     IoView,
 };
 
-use super::wasi::clocks::monotonic_clock;
+use super::wasi::clocks::{monotonic_clock, wall_clock};
 
 impl monotonic_clock::Host for EmbeddingCtx {
     fn now(&mut self) -> Result<monotonic_clock::Instant> {
@@ -30,3 +30,19 @@ impl monotonic_clock::Host for EmbeddingCtx {
         Ok(subscribe(self.table(), deadline)?)
     }
 }
+
+impl wall_clock::Host for EmbeddingCtx {
+    fn now(&mut self) -> Result<wall_clock::Datetime> {
+        let now = self.wall_clock_now();
+        Ok(wall_clock::Datetime {
+            seconds: now.as_secs(),
+            nanoseconds: now.subsec_nanos(),
+        })
+    }
+    fn resolution(&mut self) -> Result<wall_clock::Datetime> {
+        Ok(wall_clock::Datetime {
+            seconds: 0,
+            nanoseconds: 1,
+        })
+    }
+}
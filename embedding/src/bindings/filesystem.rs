@@ -1,40 +1,401 @@
+use crate::clock::Clock;
 use crate::ctx::EmbeddingCtx;
+use crate::fs::{
+    join_path, ChangeKind, ChangeLog, Dir, File, FileReader, FileWriter, Node, Preopen, Times,
+};
+use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec::Vec;
 use anyhow::Result;
+use core::time::Duration;
 use wasmtime::component::Resource;
-use wasmtime_wasi_io::streams::{DynInputStream, DynOutputStream};
+use wasmtime_wasi_io::{
+    streams::{DynInputStream, DynOutputStream},
+    IoView,
+};
 
 use super::wasi::filesystem::{preopens, types};
+use types::{DescriptorFlags, ErrorCode};
+
+/// bindgen with clause makes types::Descriptor an alias to this. A descriptor is a directory or
+/// file in an in-memory `crate::fs::Dir` tree, along with what it was opened for, and whether it
+/// was reached through a read-only preopen. Changes made through it are logged under its path,
+/// which starts with the preopen's name.
+#[derive(Clone)]
+pub struct Descriptor {
+    node: Node,
+    flags: DescriptorFlags,
+    read_only: bool,
+    path: String,
+    changes: ChangeLog,
+    max_file_size: u64,
+}
+
+impl Descriptor {
+    pub fn preopen(preopen: Preopen, changes: ChangeLog, max_file_size: u64) -> Self {
+        let flags = match preopen.read_only {
+            true => DescriptorFlags::READ,
+            false => DescriptorFlags::READ | DescriptorFlags::MUTATE_DIRECTORY,
+        };
+        Self {
+            node: Node::Dir(preopen.dir),
+            flags,
+            read_only: preopen.read_only,
+            path: preopen.name,
+            changes,
+            max_file_size,
+        }
+    }
+    fn dir(&self) -> Result<&Dir, ErrorCode> {
+        match &self.node {
+            Node::Dir(d) => Ok(d),
+            Node::File(_) => Err(ErrorCode::NotDirectory),
+        }
+    }
+    fn file(&self) -> Result<&File, ErrorCode> {
+        match &self.node {
+            Node::File(f) => Ok(f),
+            Node::Dir(_) => Err(ErrorCode::IsDirectory),
+        }
+    }
+    fn readable_file(&self) -> Result<&File, ErrorCode> {
+        let file = self.file()?;
+        if !self.flags.contains(DescriptorFlags::READ) {
+            return Err(ErrorCode::BadDescriptor);
+        }
+        Ok(file)
+    }
+    fn writable_file(&self) -> Result<&File, ErrorCode> {
+        let file = self.file()?;
+        if self.read_only {
+            return Err(ErrorCode::ReadOnly);
+        }
+        if !self.flags.contains(DescriptorFlags::WRITE) {
+            return Err(ErrorCode::BadDescriptor);
+        }
+        if self.node.is_read_only() {
+            return Err(ErrorCode::Access);
+        }
+        Ok(file)
+    }
+    /// A directory whose entries may be added, removed or renamed.
+    fn mutable_dir(&self) -> Result<&Dir, ErrorCode> {
+        let dir = self.dir()?;
+        if self.read_only {
+            return Err(ErrorCode::ReadOnly);
+        }
+        if !self.flags.contains(DescriptorFlags::MUTATE_DIRECTORY) {
+            return Err(ErrorCode::NotPermitted);
+        }
+        if self.node.is_read_only() {
+            return Err(ErrorCode::Access);
+        }
+        Ok(dir)
+    }
+    /// Changing a node's metadata, unlike its contents, only needs it to be writable.
+    fn check_writable(&self, node: &Node) -> Result<(), ErrorCode> {
+        if self.read_only {
+            return Err(ErrorCode::ReadOnly);
+        }
+        if node.is_read_only() {
+            return Err(ErrorCode::Access);
+        }
+        Ok(())
+    }
+    fn writer(&self, offset: Option<u64>, clock: Clock) -> Result<FileWriter, ErrorCode> {
+        let file = self.writable_file()?.clone();
+        let (changes, path) = (self.changes.clone(), self.path.clone());
+        let max_size = self.max_file_size;
+        Ok(FileWriter::new(
+            file, offset, max_size, clock, changes, path,
+        ))
+    }
+    fn record(&self, now: u64, kind: ChangeKind) {
+        self.changes.record(now, kind)
+    }
+    fn modified(&self, now: u64) {
+        let path = self.path.clone();
+        self.record(now, ChangeKind::Modified { path });
+    }
+    fn lookup(&self, path: &str) -> Result<Node, ErrorCode> {
+        self.dir()?.lookup(path)
+    }
+    /// The parent directory and name of an entry to be created, removed or renamed.
+    fn entry(&self, path: &str) -> Result<(Dir, String), ErrorCode> {
+        let (parent, name) = self.mutable_dir()?.parent_of(path, false)?;
+        if name == "." {
+            return Err(ErrorCode::Invalid);
+        }
+        if parent.is_read_only() {
+            return Err(ErrorCode::Access);
+        }
+        Ok((parent, name))
+    }
+
+    fn open_at(
+        &self,
+        path: &str,
+        open_flags: types::OpenFlags,
+        flags: DescriptorFlags,
+        now: u64,
+    ) -> Result<Descriptor, ErrorCode> {
+        let dir = self.dir()?;
+        let wants_write = flags.contains(DescriptorFlags::WRITE)
+            || flags.contains(DescriptorFlags::MUTATE_DIRECTORY);
+        if wants_write && self.read_only {
+            return Err(ErrorCode::ReadOnly);
+        }
+        if wants_write && !self.flags.contains(DescriptorFlags::MUTATE_DIRECTORY) {
+            return Err(ErrorCode::NotPermitted);
+        }
+        let (parent, name) = dir.parent_of(path, false)?;
+        let existing = match name.as_str() {
+            "." => Some(Node::Dir(parent.clone())),
+            _ => parent.child(&name),
+        };
+        let node = match existing {
+            Some(_)
+                if open_flags.contains(types::OpenFlags::CREATE)
+                    && open_flags.contains(types::OpenFlags::EXCLUSIVE) =>
+            {
+                return Err(ErrorCode::Exist)
+            }
+            Some(node) => node,
+            None if open_flags.contains(types::OpenFlags::CREATE) => {
+                if open_flags.contains(types::OpenFlags::DIRECTORY) || name == "." {
+                    return Err(ErrorCode::Invalid);
+                }
+                self.mutable_dir()?;
+                if parent.is_read_only() {
+                    return Err(ErrorCode::Access);
+                }
+                let file = parent.create_file(name, now);
+                let path = join_path(&self.path, path);
+                self.record(now, ChangeKind::Created { path });
+                Node::File(file)
+            }
+            None => return Err(ErrorCode::NoEntry),
+        };
+        match &node {
+            Node::File(_) if open_flags.contains(types::OpenFlags::DIRECTORY) => {
+                return Err(ErrorCode::NotDirectory)
+            }
+            Node::Dir(_) if flags.contains(DescriptorFlags::WRITE) => {
+                return Err(ErrorCode::IsDirectory)
+            }
+            Node::Dir(_) if open_flags.contains(types::OpenFlags::TRUNCATE) => {
+                return Err(ErrorCode::IsDirectory)
+            }
+            _ if wants_write && node.is_read_only() => return Err(ErrorCode::Access),
+            _ => {}
+        }
+        let opened = Descriptor {
+            node,
+            flags,
+            read_only: self.read_only,
+            path: join_path(&self.path, path),
+            changes: self.changes.clone(),
+            max_file_size: self.max_file_size,
+        };
+        if open_flags.contains(types::OpenFlags::TRUNCATE) {
+            let file = opened.writable_file()?;
+            if !file.is_empty() {
+                file.set_len(0, now, opened.max_file_size)?;
+                opened.modified(now);
+            }
+        }
+        Ok(opened)
+    }
+
+    fn create_directory_at(&self, path: &str, now: u64) -> Result<(), ErrorCode> {
+        let (parent, name) = self.entry(path)?;
+        if parent.child(&name).is_some() {
+            return Err(ErrorCode::Exist);
+        }
+        parent.create_dir(name, now);
+        let path = join_path(&self.path, path);
+        self.record(now, ChangeKind::Created { path });
+        Ok(())
+    }
+    fn remove_directory_at(&self, path: &str, now: u64) -> Result<(), ErrorCode> {
+        let (parent, name) = self.entry(path)?;
+        match parent.child(&name) {
+            Some(Node::Dir(d)) if !d.is_empty() => return Err(ErrorCode::NotEmpty),
+            Some(Node::Dir(d)) if d.is_read_only() => return Err(ErrorCode::Access),
+            Some(Node::Dir(_)) => {}
+            Some(Node::File(_)) => return Err(ErrorCode::NotDirectory),
+            None => return Err(ErrorCode::NoEntry),
+        }
+        parent.remove(&name, now);
+        let path = join_path(&self.path, path);
+        self.record(now, ChangeKind::Removed { path });
+        Ok(())
+    }
+    fn unlink_file_at(&self, path: &str, now: u64) -> Result<(), ErrorCode> {
+        let (parent, name) = self.entry(path)?;
+        match parent.child(&name) {
+            Some(node @ Node::File(_)) if node.is_read_only() => return Err(ErrorCode::Access),
+            Some(Node::File(_)) => {}
+            Some(Node::Dir(_)) => return Err(ErrorCode::IsDirectory),
+            None => return Err(ErrorCode::NoEntry),
+        }
+        parent.remove(&name, now);
+        let path = join_path(&self.path, path);
+        self.record(now, ChangeKind::Removed { path });
+        Ok(())
+    }
+    fn rename_at(
+        &self,
+        old_path: &str,
+        new_descriptor: &Descriptor,
+        new_path: &str,
+        now: u64,
+    ) -> Result<(), ErrorCode> {
+        let (old_parent, old_name) = self.entry(old_path)?;
+        let (new_parent, new_name) = new_descriptor.entry(new_path)?;
+        let node = old_parent.child(&old_name).ok_or(ErrorCode::NoEntry)?;
+        if node.is_read_only() {
+            return Err(ErrorCode::Access);
+        }
+        match (&node, new_parent.child(&new_name)) {
+            (_, Some(existing)) if existing.is_same(&node) => return Ok(()),
+            (Node::File(_), Some(Node::Dir(_))) => return Err(ErrorCode::IsDirectory),
+            (Node::Dir(_), Some(Node::File(_))) => return Err(ErrorCode::NotDirectory),
+            (Node::Dir(_), Some(Node::Dir(d))) if !d.is_empty() => return Err(ErrorCode::NotEmpty),
+            (Node::Dir(d), _) if d.contains(&new_parent) => return Err(ErrorCode::Invalid),
+            _ => {}
+        }
+        old_parent.remove(&old_name, now);
+        new_parent.insert(new_name, node, now);
+        let from = join_path(&self.path, old_path);
+        let to = join_path(&new_descriptor.path, new_path);
+        self.record(now, ChangeKind::Renamed { from, to });
+        Ok(())
+    }
+}
+
+/// bindgen with clause makes types::DirectoryEntryStream an alias to this. Entries are read from
+/// a snapshot of the directory taken when the stream was opened.
+pub struct DirectoryEntryStream(alloc::vec::IntoIter<types::DirectoryEntry>);
+
+fn descriptor_type(node: &Node) -> types::DescriptorType {
+    match node {
+        Node::Dir(_) => types::DescriptorType::Directory,
+        Node::File(_) => types::DescriptorType::RegularFile,
+    }
+}
+
+impl EmbeddingCtx {
+    fn descriptor(&mut self, fd: &Resource<types::Descriptor>) -> Result<Descriptor> {
+        Ok(self.table().get(fd)?.clone())
+    }
+    fn datetime(&self, time: u64) -> types::Datetime {
+        let wall = self.wall_clock_at(time);
+        types::Datetime {
+            seconds: wall.as_secs(),
+            nanoseconds: wall.subsec_nanos(),
+        }
+    }
+    fn stat_node(&self, node: &Node) -> types::DescriptorStat {
+        let times = node.times();
+        types::DescriptorStat {
+            type_: descriptor_type(node),
+            link_count: 1,
+            size: match node {
+                Node::File(f) => f.len(),
+                Node::Dir(_) => 0,
+            },
+            data_access_timestamp: Some(self.datetime(times.accessed)),
+            data_modification_timestamp: Some(self.datetime(times.modified)),
+            status_change_timestamp: Some(self.datetime(times.changed)),
+        }
+    }
+    /// Timestamps with out of range nanoseconds are `error-code.invalid`.
+    fn set_node_times(
+        &self,
+        node: &Node,
+        atime: types::NewTimestamp,
+        mtime: types::NewTimestamp,
+    ) -> Result<(), ErrorCode> {
+        let now = self.monotonic_now();
+        let to_clock = |new: types::NewTimestamp, old: u64| match new {
+            types::NewTimestamp::NoChange => Ok(old),
+            types::NewTimestamp::Now => Ok(now),
+            types::NewTimestamp::Timestamp(t) if t.nanoseconds >= 1_000_000_000 => {
+                Err(ErrorCode::Invalid)
+            }
+            types::NewTimestamp::Timestamp(t) => {
+                Ok(self.clock_at_wall(Duration::new(t.seconds, t.nanoseconds)))
+            }
+        };
+        let times = node.times();
+        node.set_times(Times {
+            accessed: to_clock(atime, times.accessed)?,
+            modified: to_clock(mtime, times.modified)?,
+            changed: now,
+        });
+        Ok(())
+    }
+}
 
 impl preopens::Host for EmbeddingCtx {
     fn get_directories(&mut self) -> Result<Vec<(Resource<types::Descriptor>, String)>> {
-        // Never construct a Descriptor, so all of the methods in the rest of Filesystem should be
-        // unreachable.
-        Ok(Vec::new())
+        let preopens = self.preopens().to_vec();
+        let mut directories = Vec::new();
+        for preopen in preopens {
+            let name = preopen.name.clone();
+            let changes = self.fs_changes().clone();
+            let max_file_size = self.max_file_size();
+            let descriptor = Descriptor::preopen(preopen, changes, max_file_size);
+            let fd = self.table().push(descriptor)?;
+            directories.push((fd, name));
+        }
+        Ok(directories)
     }
 }
 
 impl types::HostDescriptor for EmbeddingCtx {
     fn read_via_stream(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: u64,
+        fd: Resource<types::Descriptor>,
+        offset: u64,
     ) -> Result<Result<Resource<DynInputStream>, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        match d.readable_file() {
+            Ok(file) => {
+                let stream: DynInputStream =
+                    Box::new(FileReader::new(file.clone(), offset, self.clock().clone()));
+                Ok(Ok(self.table().push(stream)?))
+            }
+            Err(e) => Ok(Err(e)),
+        }
     }
     fn write_via_stream(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: u64,
+        fd: Resource<types::Descriptor>,
+        offset: u64,
     ) -> Result<Result<Resource<DynOutputStream>, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        match d.writer(Some(offset), self.clock().clone()) {
+            Ok(writer) => {
+                let stream: DynOutputStream = Box::new(writer);
+                Ok(Ok(self.table().push(stream)?))
+            }
+            Err(e) => Ok(Err(e)),
+        }
     }
     fn append_via_stream(
         &mut self,
-        _: Resource<types::Descriptor>,
+        fd: Resource<types::Descriptor>,
     ) -> Result<Result<Resource<DynOutputStream>, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        match d.writer(None, self.clock().clone()) {
+            Ok(writer) => {
+                let stream: DynOutputStream = Box::new(writer);
+                Ok(Ok(self.table().push(stream)?))
+            }
+            Err(e) => Ok(Err(e)),
+        }
     }
     fn advise(
         &mut self,
@@ -43,97 +404,134 @@ impl types::HostDescriptor for EmbeddingCtx {
         _: u64,
         _: types::Advice,
     ) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        Ok(Ok(()))
     }
     fn sync_data(
         &mut self,
         _: Resource<types::Descriptor>,
     ) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        Ok(Ok(()))
     }
     fn get_flags(
         &mut self,
-        _: Resource<types::Descriptor>,
+        fd: Resource<types::Descriptor>,
     ) -> Result<Result<types::DescriptorFlags, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        Ok(Ok(self.table().get(&fd)?.flags))
     }
     fn get_type(
         &mut self,
-        _: Resource<types::Descriptor>,
+        fd: Resource<types::Descriptor>,
     ) -> Result<Result<types::DescriptorType, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        Ok(Ok(descriptor_type(&self.table().get(&fd)?.node)))
     }
     fn set_size(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: u64,
+        fd: Resource<types::Descriptor>,
+        size: u64,
     ) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        let now = self.monotonic_now();
+        Ok(d.writable_file()
+            .and_then(|f| f.set_len(size, now, d.max_file_size))
+            .map(|()| d.modified(now)))
     }
     fn set_times(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: types::NewTimestamp,
-        _: types::NewTimestamp,
+        fd: Resource<types::Descriptor>,
+        atime: types::NewTimestamp,
+        mtime: types::NewTimestamp,
     ) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        Ok(d.check_writable(&d.node)
+            .and_then(|()| self.set_node_times(&d.node, atime, mtime)))
     }
     fn read(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: u64,
-        _: u64,
+        fd: Resource<types::Descriptor>,
+        len: u64,
+        offset: u64,
     ) -> Result<Result<(Vec<u8>, bool), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        let now = self.monotonic_now();
+        Ok(d.readable_file().map(|f| f.read(offset, len, now)))
     }
     fn write(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: Vec<u8>,
-        _: u64,
+        fd: Resource<types::Descriptor>,
+        buf: Vec<u8>,
+        offset: u64,
     ) -> Result<Result<u64, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        let now = self.monotonic_now();
+        Ok(d.writable_file()
+            .and_then(|f| f.write(offset, &buf, now, d.max_file_size))
+            .map(|()| {
+                d.modified(now);
+                buf.len() as u64
+            }))
     }
 
     fn read_directory(
         &mut self,
-        _: Resource<types::Descriptor>,
+        fd: Resource<types::Descriptor>,
     ) -> Result<Result<Resource<types::DirectoryEntryStream>, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        match d.dir() {
+            Ok(dir) => {
+                let entries = dir
+                    .entries()
+                    .into_iter()
+                    .map(|(name, node)| types::DirectoryEntry {
+                        type_: descriptor_type(&node),
+                        name,
+                    })
+                    .collect::<Vec<_>>();
+                let stream = DirectoryEntryStream(entries.into_iter());
+                Ok(Ok(self.table().push(stream)?))
+            }
+            Err(e) => Ok(Err(e)),
+        }
     }
     fn sync(&mut self, _: Resource<types::Descriptor>) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        Ok(Ok(()))
     }
     fn create_directory_at(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: String,
+        fd: Resource<types::Descriptor>,
+        path: String,
     ) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        Ok(d.create_directory_at(&path, self.monotonic_now()))
     }
     fn stat(
         &mut self,
-        _: Resource<types::Descriptor>,
+        fd: Resource<types::Descriptor>,
     ) -> Result<Result<types::DescriptorStat, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        Ok(Ok(self.stat_node(&d.node)))
     }
     fn stat_at(
         &mut self,
-        _: Resource<types::Descriptor>,
+        fd: Resource<types::Descriptor>,
         _: types::PathFlags,
-        _: String,
+        path: String,
     ) -> Result<Result<types::DescriptorStat, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        Ok(d.lookup(&path).map(|node| self.stat_node(&node)))
     }
     fn set_times_at(
         &mut self,
-        _: Resource<types::Descriptor>,
+        fd: Resource<types::Descriptor>,
         _: types::PathFlags,
-        _: String,
-        _: types::NewTimestamp,
-        _: types::NewTimestamp,
+        path: String,
+        atime: types::NewTimestamp,
+        mtime: types::NewTimestamp,
     ) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        Ok(d.lookup(&path).and_then(|node| {
+            d.check_writable(&node)?;
+            self.set_node_times(&node, atime, mtime)
+        }))
     }
     fn link_at(
         &mut self,
@@ -143,40 +541,50 @@ impl types::HostDescriptor for EmbeddingCtx {
         _: Resource<types::Descriptor>,
         _: String,
     ) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        // Hard links would need link counts kept on each file.
+        Ok(Err(ErrorCode::Unsupported))
     }
     fn open_at(
         &mut self,
-        _: Resource<types::Descriptor>,
+        fd: Resource<types::Descriptor>,
         _: types::PathFlags,
-        _: String,
-        _: types::OpenFlags,
-        _: types::DescriptorFlags,
+        path: String,
+        open_flags: types::OpenFlags,
+        flags: types::DescriptorFlags,
     ) -> Result<Result<Resource<types::Descriptor>, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        match d.open_at(&path, open_flags, flags, self.monotonic_now()) {
+            Ok(opened) => Ok(Ok(self.table().push(opened)?)),
+            Err(e) => Ok(Err(e)),
+        }
     }
     fn readlink_at(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: String,
+        fd: Resource<types::Descriptor>,
+        path: String,
     ) -> Result<Result<String, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        // There are no symlinks, so anything that exists is not one.
+        let d = self.descriptor(&fd)?;
+        Ok(d.lookup(&path).and(Err(ErrorCode::Invalid)))
     }
     fn remove_directory_at(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: String,
+        fd: Resource<types::Descriptor>,
+        path: String,
     ) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        Ok(d.remove_directory_at(&path, self.monotonic_now()))
     }
     fn rename_at(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: String,
-        _: Resource<types::Descriptor>,
-        _: String,
+        old_fd: Resource<types::Descriptor>,
+        old_path: String,
+        new_fd: Resource<types::Descriptor>,
+        new_path: String,
     ) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let old = self.descriptor(&old_fd)?;
+        let new = self.descriptor(&new_fd)?;
+        Ok(old.rename_at(&old_path, &new, &new_path, self.monotonic_now()))
     }
     fn symlink_at(
         &mut self,
@@ -184,57 +592,331 @@ impl types::HostDescriptor for EmbeddingCtx {
         _: String,
         _: String,
     ) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        Ok(Err(ErrorCode::Unsupported))
     }
     fn unlink_file_at(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: String,
+        fd: Resource<types::Descriptor>,
+        path: String,
     ) -> Result<Result<(), types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        Ok(d.unlink_file_at(&path, self.monotonic_now()))
     }
     fn is_same_object(
         &mut self,
-        _: Resource<types::Descriptor>,
-        _: Resource<types::Descriptor>,
+        a: Resource<types::Descriptor>,
+        b: Resource<types::Descriptor>,
     ) -> Result<bool> {
-        unreachable!("no filesystem")
+        let a = self.descriptor(&a)?;
+        let b = self.descriptor(&b)?;
+        Ok(a.node.is_same(&b.node))
     }
     fn metadata_hash(
         &mut self,
-        _: Resource<types::Descriptor>,
+        fd: Resource<types::Descriptor>,
     ) -> Result<Result<types::MetadataHashValue, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        Ok(Ok(types::MetadataHashValue {
+            lower: d.node.id(),
+            upper: 0,
+        }))
     }
     fn metadata_hash_at(
         &mut self,
-        _: Resource<types::Descriptor>,
+        fd: Resource<types::Descriptor>,
         _: types::PathFlags,
-        _: String,
+        path: String,
     ) -> Result<Result<types::MetadataHashValue, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        let d = self.descriptor(&fd)?;
+        Ok(d.lookup(&path).map(|node| types::MetadataHashValue {
+            lower: node.id(),
+            upper: 0,
+        }))
     }
 
-    fn drop(&mut self, _: Resource<types::Descriptor>) -> Result<()> {
-        unreachable!("no filesystem")
+    fn drop(&mut self, fd: Resource<types::Descriptor>) -> Result<()> {
+        self.table().delete(fd)?;
+        Ok(())
     }
 }
 impl types::HostDirectoryEntryStream for EmbeddingCtx {
     fn read_directory_entry(
         &mut self,
-        _: Resource<types::DirectoryEntryStream>,
+        this: Resource<types::DirectoryEntryStream>,
     ) -> Result<Result<Option<types::DirectoryEntry>, types::ErrorCode>> {
-        unreachable!("no filesystem")
+        Ok(Ok(self.table().get_mut(&this)?.0.next()))
     }
-    fn drop(&mut self, _: Resource<types::DirectoryEntryStream>) -> Result<()> {
-        unreachable!("no filesystem")
+    fn drop(&mut self, this: Resource<types::DirectoryEntryStream>) -> Result<()> {
+        self.table().delete(this)?;
+        Ok(())
     }
 }
 impl types::Host for EmbeddingCtx {
     fn filesystem_error_code(
         &mut self,
-        _: Resource<wasmtime_wasi_io::streams::Error>,
+        err: Resource<wasmtime_wasi_io::streams::Error>,
     ) -> Result<Option<types::ErrorCode>> {
-        Ok(None)
+        let err = self.table().get(&err)?;
+        Ok(err.downcast_ref::<types::ErrorCode>().cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ctx::InstanceConfig;
+    use crate::fs::Change;
+    use crate::runtime::Executor;
+    use alloc::vec;
+
+    fn ctx_with(dir: &Dir, read_only: bool) -> EmbeddingCtx {
+        let preopens = vec![Preopen {
+            name: String::from("/data"),
+            dir: dir.clone(),
+            read_only,
+        }];
+        let config = InstanceConfig {
+            preopens,
+            ..InstanceConfig::default()
+        };
+        EmbeddingCtx::new(Executor::new(), Clock::new(), config)
+    }
+
+    fn preopen(ctx: &mut EmbeddingCtx) -> Resource<types::Descriptor> {
+        let mut dirs = preopens::Host::get_directories(ctx).unwrap();
+        dirs.remove(0).0
+    }
+
+    // Host methods take their descriptor by value; tests reuse it.
+    fn fd(fd: &Resource<types::Descriptor>) -> Resource<types::Descriptor> {
+        Resource::new_borrow(fd.rep())
+    }
+
+    // Carrying the nanoseconds would overflow the seconds.
+    fn overflowing() -> types::NewTimestamp {
+        types::NewTimestamp::Timestamp(types::Datetime {
+            seconds: u64::MAX,
+            nanoseconds: 1_500_000_000,
+        })
+    }
+
+    #[test]
+    fn set_times_rejects_out_of_range_nanoseconds() {
+        let dir = Dir::from_files([("a.txt", "a")]).unwrap();
+        let mut ctx = ctx_with(&dir, false);
+        let root = preopen(&mut ctx);
+        let res = types::HostDescriptor::set_times(
+            &mut ctx,
+            fd(&root),
+            types::NewTimestamp::NoChange,
+            overflowing(),
+        );
+        assert_eq!(res.unwrap(), Err(ErrorCode::Invalid));
+        let res = types::HostDescriptor::set_times_at(
+            &mut ctx,
+            fd(&root),
+            types::PathFlags::empty(),
+            String::from("a.txt"),
+            overflowing(),
+            types::NewTimestamp::NoChange,
+        );
+        assert_eq!(res.unwrap(), Err(ErrorCode::Invalid));
+    }
+
+    fn open(
+        ctx: &mut EmbeddingCtx,
+        dir: &Resource<types::Descriptor>,
+        path: &str,
+        open_flags: types::OpenFlags,
+        flags: DescriptorFlags,
+    ) -> Result<Resource<types::Descriptor>, ErrorCode> {
+        types::HostDescriptor::open_at(
+            ctx,
+            fd(dir),
+            types::PathFlags::empty(),
+            String::from(path),
+            open_flags,
+            flags,
+        )
+        .unwrap()
+    }
+
+    fn change(time: u64, kind: ChangeKind) -> Change {
+        Change { time, kind }
+    }
+
+    fn path(p: &str) -> String {
+        String::from(p)
+    }
+
+    #[test]
+    fn records_writes_and_truncation_as_one_modification() {
+        let dir = Dir::from_files([("a.txt", "hello")]).unwrap();
+        let mut ctx = ctx_with(&dir, false);
+        let root = preopen(&mut ctx);
+        let rw = DescriptorFlags::READ | DescriptorFlags::WRITE;
+        let file = open(&mut ctx, &root, "a.txt", types::OpenFlags::empty(), rw).unwrap();
+
+        ctx.clock().set(10);
+        let res = types::HostDescriptor::write(&mut ctx, fd(&file), vec![b'j'], 0);
+        assert_eq!(res.unwrap(), Ok(1));
+        ctx.clock().set(20);
+        let res = types::HostDescriptor::set_size(&mut ctx, fd(&file), 3);
+        assert_eq!(res.unwrap(), Ok(()));
+
+        assert_eq!(dir.read_file("a.txt").unwrap(), b"jel");
+        let modified = ChangeKind::Modified {
+            path: path("/data/a.txt"),
+        };
+        assert_eq!(ctx.fs_changes().changes(), vec![change(20, modified)]);
+    }
+
+    #[test]
+    fn records_creation_removal_and_rename() {
+        let dir = Dir::from_files([("a.txt", "a")]).unwrap();
+        let mut ctx = ctx_with(&dir, false);
+        let root = preopen(&mut ctx);
+
+        ctx.clock().set(1);
+        let flags = DescriptorFlags::READ | DescriptorFlags::WRITE;
+        open(&mut ctx, &root, "new.txt", types::OpenFlags::CREATE, flags).unwrap();
+        ctx.clock().set(2);
+        let res = types::HostDescriptor::create_directory_at(&mut ctx, fd(&root), path("sub"));
+        assert_eq!(res.unwrap(), Ok(()));
+        ctx.clock().set(3);
+        let res = types::HostDescriptor::rename_at(
+            &mut ctx,
+            fd(&root),
+            path("a.txt"),
+            fd(&root),
+            path("sub/b.txt"),
+        );
+        assert_eq!(res.unwrap(), Ok(()));
+        ctx.clock().set(4);
+        let res = types::HostDescriptor::unlink_file_at(&mut ctx, fd(&root), path("sub/b.txt"));
+        assert_eq!(res.unwrap(), Ok(()));
+        ctx.clock().set(5);
+        let res = types::HostDescriptor::remove_directory_at(&mut ctx, fd(&root), path("sub"));
+        assert_eq!(res.unwrap(), Ok(()));
+
+        assert!(dir.get("new.txt").is_some());
+        assert!(dir.get("a.txt").is_none());
+        assert!(dir.get("sub").is_none());
+        assert_eq!(
+            ctx.fs_changes().changes(),
+            vec![
+                change(
+                    1,
+                    ChangeKind::Created {
+                        path: path("/data/new.txt")
+                    }
+                ),
+                change(
+                    2,
+                    ChangeKind::Created {
+                        path: path("/data/sub")
+                    }
+                ),
+                change(
+                    3,
+                    ChangeKind::Renamed {
+                        from: path("/data/a.txt"),
+                        to: path("/data/sub/b.txt"),
+                    }
+                ),
+                change(
+                    4,
+                    ChangeKind::Removed {
+                        path: path("/data/sub/b.txt")
+                    }
+                ),
+                change(
+                    5,
+                    ChangeKind::Removed {
+                        path: path("/data/sub")
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn set_times_updates_times_without_recording_a_change() {
+        let dir = Dir::from_files([("a.txt", "a")]).unwrap();
+        let mut ctx = ctx_with(&dir, false);
+        let root = preopen(&mut ctx);
+        let file = open(
+            &mut ctx,
+            &root,
+            "a.txt",
+            types::OpenFlags::empty(),
+            DescriptorFlags::READ,
+        )
+        .unwrap();
+
+        ctx.clock().set(7);
+        let res = types::HostDescriptor::set_times(
+            &mut ctx,
+            fd(&file),
+            types::NewTimestamp::NoChange,
+            types::NewTimestamp::Now,
+        );
+        assert_eq!(res.unwrap(), Ok(()));
+
+        let times = dir.get("a.txt").unwrap().times();
+        assert_eq!((times.accessed, times.modified, times.changed), (0, 7, 7));
+        assert_eq!(ctx.fs_changes().changes(), vec![]);
+    }
+
+    #[test]
+    fn read_only_preopen_denies_mutations_and_records_nothing() {
+        let dir = Dir::from_files([("a.txt", "a"), ("sub/b.txt", "b")]).unwrap();
+        let mut ctx = ctx_with(&dir, true);
+        let root = preopen(&mut ctx);
+        let rw = DescriptorFlags::READ | DescriptorFlags::WRITE;
+
+        let res = open(&mut ctx, &root, "a.txt", types::OpenFlags::empty(), rw);
+        assert_eq!(res.err(), Some(ErrorCode::ReadOnly));
+        let res = open(&mut ctx, &root, "new.txt", types::OpenFlags::CREATE, rw);
+        assert_eq!(res.err(), Some(ErrorCode::ReadOnly));
+
+        let file = open(
+            &mut ctx,
+            &root,
+            "a.txt",
+            types::OpenFlags::empty(),
+            DescriptorFlags::READ,
+        )
+        .unwrap();
+        let res = types::HostDescriptor::write(&mut ctx, fd(&file), vec![b'x'], 0);
+        assert_eq!(res.unwrap(), Err(ErrorCode::ReadOnly));
+        let res = types::HostDescriptor::set_size(&mut ctx, fd(&file), 0);
+        assert_eq!(res.unwrap(), Err(ErrorCode::ReadOnly));
+        let res = types::HostDescriptor::set_times(
+            &mut ctx,
+            fd(&file),
+            types::NewTimestamp::Now,
+            types::NewTimestamp::Now,
+        );
+        assert_eq!(res.unwrap(), Err(ErrorCode::ReadOnly));
+
+        let res = types::HostDescriptor::create_directory_at(&mut ctx, fd(&root), path("new"));
+        assert_eq!(res.unwrap(), Err(ErrorCode::ReadOnly));
+        let res = types::HostDescriptor::unlink_file_at(&mut ctx, fd(&root), path("a.txt"));
+        assert_eq!(res.unwrap(), Err(ErrorCode::ReadOnly));
+        let res = types::HostDescriptor::remove_directory_at(&mut ctx, fd(&root), path("sub"));
+        assert_eq!(res.unwrap(), Err(ErrorCode::ReadOnly));
+        let res = types::HostDescriptor::rename_at(
+            &mut ctx,
+            fd(&root),
+            path("a.txt"),
+            fd(&root),
+            path("c.txt"),
+        );
+        assert_eq!(res.unwrap(), Err(ErrorCode::ReadOnly));
+
+        assert_eq!(dir.read_file("a.txt").unwrap(), b"a");
+        assert!(dir.get("sub/b.txt").is_some());
+        assert_eq!(ctx.fs_changes().changes(), vec![]);
     }
 }
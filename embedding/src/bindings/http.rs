@@ -71,7 +71,7 @@ impl types::HostIncomingRequest for EmbeddingCtx {
         let table = self.table();
         // Inner result: only return the IncomingBody resource once. Subsequent returns error.
         if let Some(body) = table.get_mut(&this)?.body.take() {
-            Ok(Ok(table.push(IncomingBodyResource(body))?))
+            Ok(Ok(table.push(IncomingBodyResource::new(body))?))
         } else {
             Ok(Err(()))
         }
@@ -85,7 +85,8 @@ impl types::HostIncomingRequest for EmbeddingCtx {
 pub struct OutgoingResponseResource {
     resp: crate::http::OutgoingResponse,
     headers: Rc<crate::http::ImmutFields>,
-    body: Option<crate::http::OutgoingBody>,
+    body: crate::http::OutgoingBody,
+    body_taken: bool,
 }
 // SAFETY: single-threaded embedding only
 unsafe impl Send for OutgoingResponseResource {}
@@ -100,7 +101,8 @@ impl OutgoingResponseResource {
         Self {
             resp,
             headers: Rc::new(headers),
-            body: Some(body),
+            body,
+            body_taken: false,
         }
     }
 }
@@ -114,7 +116,7 @@ impl types::HostOutgoingResponse for EmbeddingCtx {
         Ok(self.table().push(OutgoingResponseResource::new(
             crate::http::OutgoingResponse::new(),
             headers,
-            crate::http::OutgoingBody {},
+            crate::http::OutgoingBody::new(self.clock().clone()),
         ))?)
     }
     fn status_code(
@@ -144,10 +146,14 @@ impl types::HostOutgoingResponse for EmbeddingCtx {
     ) -> Result<Result<Resource<types::OutgoingBody>, ()>> {
         let table = self.table();
         // Inner result: only return the OutgoingBody resource once. Subsequent returns error.
-        if let Some(body) = table.get_mut(&this)?.body.take() {
-            Ok(Ok(table.push(OutgoingBodyResource(body))?))
-        } else {
+        // The body is shared, rather than taken, so that the embedding can see what gets written.
+        let this = table.get_mut(&this)?;
+        if this.body_taken {
             Ok(Err(()))
+        } else {
+            this.body_taken = true;
+            let body = this.body.clone();
+            Ok(Ok(table.push(OutgoingBodyResource::new(body))?))
         }
     }
     fn drop(&mut self, this: Resource<types::OutgoingResponse>) -> Result<()> {
@@ -159,7 +165,8 @@ impl types::HostOutgoingResponse for EmbeddingCtx {
 pub struct OutgoingRequestResource {
     req: crate::http::OutgoingRequest,
     headers: Rc<crate::http::ImmutFields>,
-    body: Option<crate::http::OutgoingBody>,
+    body: crate::http::OutgoingBody,
+    body_taken: bool,
 }
 // SAFETY: single-threaded embedding only
 unsafe impl Send for OutgoingRequestResource {}
@@ -173,7 +180,8 @@ impl OutgoingRequestResource {
         Self {
             req,
             headers: Rc::new(headers),
-            body: Some(body),
+            body,
+            body_taken: false,
         }
     }
 }
@@ -187,7 +195,7 @@ impl types::HostOutgoingRequest for EmbeddingCtx {
         Ok(self.table().push(OutgoingRequestResource::new(
             crate::http::OutgoingRequest::new(),
             headers,
-            crate::http::OutgoingBody {},
+            crate::http::OutgoingBody::new(self.clock().clone()),
         ))?)
     }
     fn body(
@@ -196,10 +204,14 @@ impl types::HostOutgoingRequest for EmbeddingCtx {
     ) -> Result<Result<Resource<types::OutgoingBody>, ()>> {
         let table = self.table();
         // Inner result: only return the OutgoingBody resource once. Subsequent returns error.
-        if let Some(body) = table.get_mut(&this)?.body.take() {
-            Ok(Ok(table.push(OutgoingBodyResource(body))?))
-        } else {
+        // The body is shared, rather than taken, so that the embedding can see what gets written.
+        let this = table.get_mut(&this)?;
+        if this.body_taken {
             Ok(Err(()))
+        } else {
+            this.body_taken = true;
+            let body = this.body.clone();
+            Ok(Ok(table.push(OutgoingBodyResource::new(body))?))
         }
     }
     fn method(&mut self, this: Resource<types::OutgoingRequest>) -> Result<types::Method> {
@@ -302,7 +314,7 @@ impl types::HostIncomingResponse for EmbeddingCtx {
         let table = self.table();
         // Inner result: only return the IncomingBody resource once. Subsequent returns error.
         if let Some(body) = table.get_mut(&this)?.body.take() {
-            Ok(Ok(table.push(IncomingBodyResource(body))?))
+            Ok(Ok(table.push(IncomingBodyResource::new(body))?))
         } else {
             Ok(Err(()))
         }
@@ -341,18 +353,13 @@ impl types::HostFutureIncomingResponse for EmbeddingCtx {
     }
 }
 
-pub struct IncomingBodyResource(crate::http::IncomingBody);
-
-struct TrapOnRead;
-
-impl wasmtime_wasi_io::streams::InputStream for TrapOnRead {
-    fn read(&mut self, _: usize) -> Result<bytes::Bytes, wasmtime_wasi_io::streams::StreamError> {
-        Err(wasmtime_wasi_io::streams::StreamError::trap("cant read!!!"))
-    }
+pub struct IncomingBodyResource {
+    body: Option<crate::http::IncomingBody>,
 }
-#[wasmtime_wasi_io::async_trait]
-impl wasmtime_wasi_io::poll::Pollable for TrapOnRead {
-    async fn ready(&mut self) {}
+impl IncomingBodyResource {
+    pub fn new(body: crate::http::IncomingBody) -> Self {
+        Self { body: Some(body) }
+    }
 }
 
 impl types::HostIncomingBody for EmbeddingCtx {
@@ -360,15 +367,21 @@ impl types::HostIncomingBody for EmbeddingCtx {
         &mut self,
         this: Resource<types::IncomingBody>,
     ) -> Result<Result<Resource<DynInputStream>, ()>> {
-        let _this = self.table().get(&this)?;
-        let input_stream: wasmtime_wasi_io::streams::DynInputStream = Box::new(TrapOnRead);
-        Ok(Ok(self.table().push(input_stream)?))
+        let executor = self.executor().clone();
+        let table = self.table();
+        // Inner result: only return the input-stream resource once. Subsequent returns error.
+        if let Some(body) = table.get_mut(&this)?.body.take() {
+            Ok(Ok(table.push(body.into_stream(&executor))?))
+        } else {
+            Ok(Err(()))
+        }
     }
     fn finish(
         &mut self,
-        _: Resource<types::IncomingBody>,
+        this: Resource<types::IncomingBody>,
     ) -> Result<Resource<types::FutureTrailers>> {
-        todo!()
+        self.table().delete(this)?;
+        Ok(self.table().push(FutureTrailers { gone: false })?)
     }
     fn drop(&mut self, this: Resource<types::IncomingBody>) -> Result<()> {
         self.table().delete(this)?;
@@ -376,28 +389,17 @@ impl types::HostIncomingBody for EmbeddingCtx {
     }
 }
 
-pub struct OutgoingBodyResource(crate::http::OutgoingBody);
-
-struct TrapOnWrite;
-
-impl wasmtime_wasi_io::streams::OutputStream for TrapOnWrite {
-    fn check_write(&mut self) -> Result<usize, wasmtime_wasi_io::streams::StreamError> {
-        Ok(usize::MAX)
-    }
-    fn write(&mut self, bs: bytes::Bytes) -> Result<(), wasmtime_wasi_io::streams::StreamError> {
-        Err(wasmtime_wasi_io::streams::StreamError::Trap(
-            anyhow::anyhow!("cant write!!! {bs:?}"),
-        ))
-    }
-    fn flush(&mut self) -> Result<(), wasmtime_wasi_io::streams::StreamError> {
-        Err(wasmtime_wasi_io::streams::StreamError::trap(
-            "cant write!!!",
-        ))
-    }
+pub struct OutgoingBodyResource {
+    body: crate::http::OutgoingBody,
+    stream_taken: bool,
 }
-#[wasmtime_wasi_io::async_trait]
-impl wasmtime_wasi_io::poll::Pollable for TrapOnWrite {
-    async fn ready(&mut self) {}
+impl OutgoingBodyResource {
+    pub fn new(body: crate::http::OutgoingBody) -> Self {
+        Self {
+            body,
+            stream_taken: false,
+        }
+    }
 }
 
 impl types::HostOutgoingBody for EmbeddingCtx {
@@ -405,23 +407,32 @@ impl types::HostOutgoingBody for EmbeddingCtx {
         &mut self,
         this: Resource<types::OutgoingBody>,
     ) -> Result<Result<Resource<DynOutputStream>, ()>> {
-        let _this = self.table().get(&this)?;
-        let output_stream: wasmtime_wasi_io::streams::DynOutputStream = Box::new(TrapOnWrite);
-        Ok(Ok(self.table().push(output_stream)?))
+        let table = self.table();
+        // Inner result: only return the output-stream resource once. Subsequent returns error.
+        let this = table.get_mut(&this)?;
+        if this.stream_taken {
+            Ok(Err(()))
+        } else {
+            this.stream_taken = true;
+            let output_stream: DynOutputStream = Box::new(this.body.clone());
+            Ok(Ok(table.push(output_stream)?))
+        }
     }
     fn finish(
         &mut self,
         this: Resource<types::OutgoingBody>,
         trailers: Option<Resource<types::Trailers>>,
     ) -> Result<Result<(), types::ErrorCode>> {
-        self.table().delete(this)?;
+        let this = self.table().delete(this)?;
+        this.body.finish();
         if let Some(trailers) = trailers {
             self.table().delete(trailers)?;
         }
         Ok(Ok(()))
     }
     fn drop(&mut self, this: Resource<types::OutgoingBody>) -> Result<()> {
-        self.table().delete(this)?;
+        let this = self.table().delete(this)?;
+        this.body.drop_early();
         Ok(())
     }
 }
@@ -613,14 +624,24 @@ impl types::HostResponseOutparam for EmbeddingCtx {
         let this = self.table().delete(this)?;
         match result {
             Ok(out_resp) => {
-                let resp = self.table().delete(out_resp)?;
-                let headers = Rc::try_unwrap(resp.headers).map_err(|rc| {
+                let OutgoingResponseResource {
+                    resp,
+                    headers,
+                    body,
+                    body_taken,
+                } = self.table().delete(out_resp)?;
+                let headers = Rc::try_unwrap(headers).map_err(|rc| {
                     anyhow!(
                         "{} outstanding references to mut fields, should be impossible",
                         Rc::strong_count(&rc)
                     )
                 })?;
-                this.0.send_success(resp.resp, headers, resp.body);
+                // The response resource is gone, so a body the guest never
+                // took can never be written: send it empty.
+                if !body_taken {
+                    body.finish();
+                }
+                this.0.send_success(resp, headers, body);
             }
             Err(e) => {
                 this.0.send_error(e);
@@ -695,7 +716,7 @@ impl types::HostRequestOptions for EmbeddingCtx {
         val: Option<monotonic_clock::Duration>,
     ) -> Result<Result<(), ()>> {
         let this = self.table().get_mut(&this)?;
-        this.0.first_byte_timeout = val.map(from_wasi_duration);
+        this.0.between_bytes_timeout = val.map(from_wasi_duration);
         Ok(Ok(()))
     }
     fn drop(&mut self, this: Resource<types::RequestOptions>) -> Result<()> {
@@ -720,7 +741,17 @@ impl outgoing_handler::Host for EmbeddingCtx {
         request: Resource<types::OutgoingRequest>,
         options: Option<Resource<types::RequestOptions>>,
     ) -> Result<Result<Resource<types::FutureIncomingResponse>, types::ErrorCode>> {
-        let OutgoingRequestResource { req, headers, body } = self.table().delete(request)?;
+        let OutgoingRequestResource {
+            req,
+            headers,
+            body,
+            body_taken,
+        } = self.table().delete(request)?;
+        // The request resource is gone, so a body the guest never took can
+        // never be written: send it empty.
+        if !body_taken {
+            body.finish();
+        }
         let headers = Rc::try_unwrap(headers).map_err(|rc| {
             anyhow!(
                 "{} outstanding references to immut fields, should be impossible",
@@ -730,9 +761,15 @@ impl outgoing_handler::Host for EmbeddingCtx {
         let options = options
             .map(|options| self.table().delete(options))
             .transpose()?
-            .map(|o| o.0);
-        let resp = FutureIncomingResponse::spawn(self.executor(), async move {
-            let (resp, headers, body) = req.send(headers, body, options).await?;
+            .map(|o| o.0)
+            .unwrap_or_default()
+            .or(self.default_request_options());
+        let Some(handler) = self.outgoing_handler() else {
+            return Ok(Err(types::ErrorCode::HttpRequestDenied));
+        };
+        let timer = self.timer();
+        let resp = FutureIncomingResponse::spawn(self.executor(), "outgoing-handler", async move {
+            let (resp, headers, body) = req.send(&*handler, timer, headers, body, options).await?;
             Ok(IncomingResponseResource::new(resp, headers, body))
         });
         Ok(Ok(self.table().push(resp)?))
@@ -2,15 +2,29 @@ use crate::ctx::EmbeddingCtx;
 use alloc::vec::Vec;
 use anyhow::Result;
 
-use super::wasi::random::random;
+use super::wasi::random::{insecure, insecure_seed, random};
 
 impl random::Host for EmbeddingCtx {
     fn get_random_bytes(&mut self, len: u64) -> Result<Vec<u8>> {
-        let mut vec = Vec::new();
-        vec.resize(len as usize, 0u8);
-        Ok(vec)
+        Ok(self.random().bytes(len as usize))
     }
     fn get_random_u64(&mut self) -> Result<u64> {
-        Ok(0)
+        Ok(self.random().u64())
+    }
+}
+
+impl insecure::Host for EmbeddingCtx {
+    fn get_insecure_random_bytes(&mut self, len: u64) -> Result<Vec<u8>> {
+        Ok(self.random().bytes(len as usize))
+    }
+    fn get_insecure_random_u64(&mut self) -> Result<u64> {
+        Ok(self.random().u64())
+    }
+}
+
+impl insecure_seed::Host for EmbeddingCtx {
+    fn insecure_seed(&mut self) -> Result<(u64, u64)> {
+        let random = self.random();
+        Ok((random.u64(), random.u64()))
     }
 }
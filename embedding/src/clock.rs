@@ -4,8 +4,9 @@ use core::cell::Cell;
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll};
+use core::time::Duration;
 
-use crate::runtime::Executor;
+use crate::runtime::{DeadlineKey, Executor};
 
 use wasmtime_wasi_io::poll::Pollable;
 
@@ -27,11 +28,44 @@ impl Clock {
 unsafe impl Send for Clock {}
 unsafe impl Sync for Clock {}
 
+/// A handle on an instance's virtual clock, for host code that needs to
+/// wait on it. Clock values are in nanoseconds.
 #[derive(Debug, Clone)]
+pub struct Timer {
+    executor: Executor,
+    clock: Clock,
+}
+impl Timer {
+    pub(crate) fn new(executor: Executor, clock: Clock) -> Self {
+        Self { executor, clock }
+    }
+    pub fn now(&self) -> u64 {
+        self.clock.get()
+    }
+    /// A Deadline that is ready once the clock reaches `due`.
+    pub fn at(&self, due: u64) -> Deadline {
+        Deadline::new(self.executor.clone(), self.clock.clone(), due)
+    }
+    /// A Deadline that is ready once `duration` has passed on the clock.
+    pub fn after(&self, duration: Duration) -> Deadline {
+        let duration = duration.as_nanos().try_into().unwrap_or(u64::MAX);
+        self.at(self.now().saturating_add(duration))
+    }
+}
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for Timer {}
+unsafe impl Sync for Timer {}
+
+/// A future that is ready once the clock reaches its due time. While it is
+/// pending, it holds a registration with the executor, which is kept pointed
+/// at the latest waker and removed when the Deadline is dropped, so the
+/// executor only reports deadlines that something is still waiting on.
+#[derive(Debug)]
 pub struct Deadline {
     executor: Executor,
     clock: Clock,
     due: u64,
+    registration: Option<DeadlineKey>,
 }
 impl Deadline {
     pub fn new(executor: Executor, clock: Clock, due: u64) -> Self {
@@ -39,18 +73,45 @@ impl Deadline {
             executor,
             clock,
             due,
+            registration: None,
         }
     }
+    pub fn due(&self) -> u64 {
+        self.due
+    }
+}
+// Each clone waits, and registers, on its own.
+impl Clone for Deadline {
+    fn clone(&self) -> Self {
+        Self::new(self.executor.clone(), self.clock.clone(), self.due)
+    }
 }
 impl Future for Deadline {
     type Output = ();
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let now = self.clock.get();
-        if now < self.due {
-            self.executor.push_deadline(self.due, cx.waker().clone());
-            Poll::Pending
-        } else {
-            Poll::Ready(())
+        let this = self.get_mut();
+        if this.clock.get() >= this.due {
+            if let Some(key) = this.registration.take() {
+                this.executor.cancel_deadline(key);
+            }
+            return Poll::Ready(());
+        }
+        match this.registration {
+            Some(key) => this.executor.update_deadline(key, cx.waker()),
+            None => {
+                let key = this
+                    .executor
+                    .register_deadline(this.due, cx.waker().clone());
+                this.registration = Some(key);
+            }
+        }
+        Poll::Pending
+    }
+}
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        if let Some(key) = self.registration.take() {
+            self.executor.cancel_deadline(key);
         }
     }
 }
@@ -1,7 +1,16 @@
-use crate::clock::{Clock, Deadline};
+use crate::clock::{Clock, Deadline, Timer};
+use crate::fs::{ChangeLog, Preopen};
+use crate::http::{OutgoingHandler, RequestOptions};
+use crate::instance::DEFAULT_MAX_FILE_SIZE;
+use crate::output::{CapturedOutput, OutputLimit, OutputLog, OutputSink, Stdio, StdioWriter};
+use crate::random::{EntropyFn, RandomSource};
 use crate::runtime::Executor;
-use crate::streams::{NeverReadable, TimestampedWrites};
+use crate::streams::ChunkFeed;
 use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bytes::Bytes;
+use core::time::Duration;
 use wasmtime::component::ResourceTable;
 use wasmtime_wasi_io::{
     poll::Pollable,
@@ -16,50 +25,164 @@ impl<T: IoView> IoView for EImpl<T> {
     }
 }
 
+/// Settings that may differ between instances of the same component.
+#[derive(Clone, Default)]
+pub struct InstanceConfig {
+    pub outgoing_handler: Option<Arc<dyn OutgoingHandler>>,
+    pub default_request_options: RequestOptions,
+    pub arguments: Vec<String>,
+    pub environment: Vec<(String, String)>,
+    pub initial_cwd: Option<String>,
+    pub random_seed: u64,
+    pub entropy: Option<EntropyFn>,
+    pub wall_clock_epoch: Duration,
+    pub preopens: Vec<Preopen>,
+    pub max_file_size: Option<u64>,
+    pub stdin: Option<ChunkFeed>,
+    pub output_sink: Option<Arc<dyn OutputSink>>,
+    pub stdout_limit: OutputLimit,
+    pub stderr_limit: OutputLimit,
+    pub fuel: Option<u64>,
+    pub fuel_yield_interval: Option<u64>,
+}
+
 pub struct EmbeddingCtx {
     table: ResourceTable,
     executor: Executor,
     clock: Clock,
-    stdout: TimestampedWrites,
-    stderr: TimestampedWrites,
+    stdin: ChunkFeed,
+    output: CapturedOutput,
+    stdout: StdioWriter,
+    stderr: StdioWriter,
+    random: RandomSource,
+    fs_changes: ChangeLog,
+    config: InstanceConfig,
 }
 
 impl EmbeddingCtx {
-    pub fn new(executor: Executor, clock: Clock) -> Self {
-        let stdout = TimestampedWrites::new(clock.clone());
-        let stderr = TimestampedWrites::new(clock.clone());
+    pub fn new(executor: Executor, clock: Clock, config: InstanceConfig) -> Self {
+        // Without any stdin from the embedder, the guest reads an empty, closed stream.
+        let stdin = config
+            .stdin
+            .clone()
+            .unwrap_or_else(|| ChunkFeed::full(Bytes::new()));
+        stdin.watch(&executor, "stdin");
+        let output = CapturedOutput::new(
+            config.output_sink.clone(),
+            config.stdout_limit,
+            config.stderr_limit,
+        );
+        let stdout = StdioWriter::new(
+            Stdio::Stdout,
+            clock.clone(),
+            executor.clone(),
+            output.clone(),
+        );
+        let stderr = StdioWriter::new(
+            Stdio::Stderr,
+            clock.clone(),
+            executor.clone(),
+            output.clone(),
+        );
+        let random = RandomSource::new(config.random_seed, config.entropy.clone());
 
         EmbeddingCtx {
             table: ResourceTable::new(),
             executor,
             clock,
+            stdin,
+            output,
             stdout,
             stderr,
+            random,
+            fs_changes: ChangeLog::default(),
+            config,
         }
     }
 
     pub fn report(&self) -> String {
         use core::fmt::Write;
         let mut out = String::new();
-        core::write!(&mut out, "stdout:\n").unwrap();
-        self.stdout.report(&mut out).unwrap();
-        core::write!(&mut out, "stderr:\n").unwrap();
-        self.stderr.report(&mut out).unwrap();
+        self.output().report(&mut out).unwrap();
+        let changes = self.fs_changes.changes();
+        if !self.config.preopens.is_empty() || !changes.is_empty() {
+            core::writeln!(&mut out, "filesystem changes:").unwrap();
+            for change in changes {
+                core::writeln!(&mut out, "{change}").unwrap();
+            }
+        }
         out
     }
+    pub(crate) fn output(&self) -> OutputLog {
+        self.output.log()
+    }
+    pub(crate) fn captured_output(&self) -> &CapturedOutput {
+        &self.output
+    }
     pub(crate) fn monotonic_now(&self) -> u64 {
         let now = self.clock.get();
         //println!("wasm told now is: {now}");
         now
     }
+    /// The wall clock reads as the configured epoch, plus however far the
+    /// virtual clock has advanced.
+    pub(crate) fn wall_clock_now(&self) -> Duration {
+        self.wall_clock_at(self.clock.get())
+    }
+    /// The wall clock time at a given reading of the virtual clock.
+    pub(crate) fn wall_clock_at(&self, time: u64) -> Duration {
+        self.config.wall_clock_epoch + Duration::from_nanos(time)
+    }
+    /// The virtual clock reading at a given wall clock time. Times before the
+    /// epoch read as 0.
+    pub(crate) fn clock_at_wall(&self, wall: Duration) -> u64 {
+        let since_epoch = wall.saturating_sub(self.config.wall_clock_epoch);
+        u64::try_from(since_epoch.as_nanos()).unwrap_or(u64::MAX)
+    }
     pub(crate) fn monotonic_timer(&self, deadline: u64) -> impl Pollable {
         Deadline::new(self.executor.clone(), self.clock.clone(), deadline)
     }
+    pub(crate) fn timer(&self) -> Timer {
+        Timer::new(self.executor.clone(), self.clock.clone())
+    }
     pub(crate) fn executor(&self) -> &Executor {
         &self.executor
     }
+    pub(crate) fn clock(&self) -> &Clock {
+        &self.clock
+    }
+    pub(crate) fn outgoing_handler(&self) -> Option<Arc<dyn OutgoingHandler>> {
+        self.config.outgoing_handler.clone()
+    }
+    pub(crate) fn default_request_options(&self) -> RequestOptions {
+        self.config.default_request_options
+    }
+    pub(crate) fn arguments(&self) -> &[String] {
+        &self.config.arguments
+    }
+    pub(crate) fn environment(&self) -> &[(String, String)] {
+        &self.config.environment
+    }
+    pub(crate) fn initial_cwd(&self) -> Option<&str> {
+        self.config.initial_cwd.as_deref()
+    }
+    pub(crate) fn preopens(&self) -> &[Preopen] {
+        &self.config.preopens
+    }
+    pub(crate) fn max_file_size(&self) -> u64 {
+        self.config.max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE)
+    }
+    pub(crate) fn fs_changes(&self) -> &ChangeLog {
+        &self.fs_changes
+    }
+    pub(crate) fn random(&mut self) -> &mut RandomSource {
+        &mut self.random
+    }
     pub(crate) fn stdin(&self) -> impl InputStream {
-        NeverReadable
+        self.stdin.clone()
+    }
+    pub(crate) fn stdin_feed(&self) -> &ChunkFeed {
+        &self.stdin
     }
     pub(crate) fn stdout(&self) -> impl OutputStream {
         self.stdout.clone()
@@ -72,7 +195,6 @@ impl EmbeddingCtx {
     // fn create_fields(&self) -> impl Fields
     // fn create_outgoing_request(&self) -> (impl OutgoingRequest, impl OutgoingBody)
     // fn create_outgoing_response(&self) -> (impl OutgoingResponse, impl OutgoingBody)
-    // fn outbound_http(&self, outgoing request) -> mailbox<incoming response>
 }
 impl wasmtime_wasi_io::IoView for EmbeddingCtx {
     fn table(&mut self) -> &mut ResourceTable {
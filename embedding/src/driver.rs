@@ -0,0 +1,104 @@
+use crate::{Completion, RunningComponent, Scheduler};
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
+
+/// The host's own sense of time, for driving a `Scheduler` in real time.
+/// Implemented by the embedder, so that real time works without `std`.
+pub trait HostTime {
+    /// The host's monotonic time, in nanoseconds.
+    fn now(&self) -> u64;
+    /// Block until the host's monotonic time reaches `deadline`, or
+    /// indefinitely if there is none. Return early when woken by an
+    /// external event, such as more of a request body arriving.
+    fn sleep(&self, deadline: Option<u64>);
+}
+
+/// Drives a `Scheduler` with real time instead of virtual time: the clock
+/// follows the host's monotonic time, and when nothing is ready to run the
+/// driver sleeps until the earliest deadline or an external wake.
+///
+/// The same component runs unchanged under virtual time, by stepping and
+/// advancing the scheduler directly, or under a driver.
+pub struct RealTimeDriver<H> {
+    scheduler: Scheduler,
+    host: H,
+    // The host time and clock reading when the driver was created. The clock
+    // advances from there in step with the host.
+    host_origin: u64,
+    clock_origin: u64,
+}
+
+impl<H: HostTime> RealTimeDriver<H> {
+    pub fn new(scheduler: &Scheduler, host: H) -> Self {
+        RealTimeDriver {
+            scheduler: scheduler.clone(),
+            host_origin: host.now(),
+            clock_origin: scheduler.now(),
+            host,
+        }
+    }
+
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    /// Advance the clock to the host's current time, waking any deadlines
+    /// that have passed. The clock never moves backwards.
+    pub fn sync(&self) {
+        let elapsed = self.host.now().saturating_sub(self.host_origin);
+        let now = self.clock_origin.saturating_add(elapsed);
+        if now > self.scheduler.now() {
+            self.scheduler.advance_clock(now);
+        }
+    }
+
+    /// Bring the clock up to date and step the scheduler. If no task was
+    /// ready, sleep until the earliest deadline is due on the host, or until
+    /// woken. Returns how many tasks ran, or an error if the scheduler is
+    /// quiescent, since sleeping would then never end.
+    pub fn turn(&self) -> Result<usize> {
+        self.sync();
+        let runs = self.scheduler.step();
+        if runs == 0 {
+            self.sleep()?;
+        }
+        Ok(runs)
+    }
+
+    /// Turn until `running` completes, giving the same report and result as
+    /// `RunningComponent::check_complete`. Fails if the scheduler becomes
+    /// quiescent first, listing what each task is blocked on.
+    pub fn run(&self, running: &mut RunningComponent) -> Result<(String, Result<Completion>)> {
+        loop {
+            self.sync();
+            let runs = self.scheduler.step();
+            // The component may have completed already, or on this step.
+            if let Some(complete) = running.check_complete() {
+                return Ok(complete);
+            }
+            if runs == 0 {
+                self.sleep()?;
+            }
+        }
+    }
+
+    fn sleep(&self) -> Result<()> {
+        if self.scheduler.is_quiescent() {
+            let blocked = self
+                .scheduler
+                .blocked_tasks()
+                .iter()
+                .map(|task| task.to_string())
+                .collect::<Vec<_>>();
+            bail!("scheduler is deadlocked: {}", blocked.join("; "));
+        }
+        let wake_at = self.scheduler.earliest_deadline().map(|deadline| {
+            let until = deadline.saturating_sub(self.clock_origin);
+            self.host_origin.saturating_add(until)
+        });
+        self.host.sleep(wake_at);
+        Ok(())
+    }
+}
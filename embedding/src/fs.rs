@@ -0,0 +1,772 @@
+pub use crate::bindings::wasi::filesystem::types::ErrorCode;
+
+use crate::clock::Clock;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use anyhow::{anyhow, bail};
+use bytes::Bytes;
+use core::cell::RefCell;
+use wasmtime_wasi_io::poll::Pollable;
+use wasmtime_wasi_io::streams::{InputStream, OutputStream, StreamError, StreamResult};
+
+/// A directory made available to an instance through
+/// `wasi:filesystem/preopens`. See `InstanceBuilder::preopen`.
+#[derive(Clone)]
+pub struct Preopen {
+    pub name: String,
+    pub dir: Dir,
+    pub read_only: bool,
+}
+
+/// A change the guest made to a filesystem, at a time on the virtual Clock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub time: u64,
+    pub kind: ChangeKind,
+}
+
+/// Paths start with the name of the preopen they were changed through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created { path: String },
+    Modified { path: String },
+    Removed { path: String },
+    Renamed { from: String, to: String },
+}
+
+impl core::fmt::Display for Change {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.kind {
+            ChangeKind::Created { path } => write!(f, "{:08} created {path}", self.time),
+            ChangeKind::Modified { path } => write!(f, "{:08} modified {path}", self.time),
+            ChangeKind::Removed { path } => write!(f, "{:08} removed {path}", self.time),
+            ChangeKind::Renamed { from, to } => {
+                write!(f, "{:08} renamed {from} to {to}", self.time)
+            }
+        }
+    }
+}
+
+/// Every change made by one instance, in the order they were made.
+#[derive(Clone, Default)]
+pub(crate) struct ChangeLog(Rc<RefCell<Vec<Change>>>);
+
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for ChangeLog {}
+unsafe impl Sync for ChangeLog {}
+
+impl ChangeLog {
+    pub fn record(&self, time: u64, kind: ChangeKind) {
+        let mut changes = self.0.borrow_mut();
+        // A run of writes to the same file is one modification, at the time of the last write.
+        if let ChangeKind::Modified { path } = &kind {
+            if let Some(last) = changes.last_mut() {
+                if matches!(&last.kind, ChangeKind::Modified { path: p } if p == path) {
+                    last.time = time;
+                    return;
+                }
+            }
+        }
+        changes.push(Change { time, kind });
+    }
+    pub fn changes(&self) -> Vec<Change> {
+        self.0.borrow().clone()
+    }
+}
+
+/// Join a path that has already been resolved relative to `base` onto it, so that `.` and `..`
+/// components are gone.
+pub(crate) fn join_path(base: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            c => components.push(c),
+        }
+    }
+    let mut joined = String::from(base);
+    for component in components {
+        if !joined.ends_with('/') {
+            joined.push('/');
+        }
+        joined.push_str(component);
+    }
+    joined
+}
+
+/// Access, modification and status change times, as values of the virtual
+/// Clock.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Times {
+    pub accessed: u64,
+    pub modified: u64,
+    pub changed: u64,
+}
+impl Times {
+    fn at(now: u64) -> Self {
+        Times {
+            accessed: now,
+            modified: now,
+            changed: now,
+        }
+    }
+    fn modify(&mut self, now: u64) {
+        self.modified = now;
+        self.changed = now;
+    }
+}
+
+/// A directory in an in-memory filesystem. Fill one in before creating an
+/// instance, and attach it to the instance as a preopen with
+/// `InstanceBuilder::preopen`.
+///
+/// Clones refer to the same directory, so the embedder can keep a clone to
+/// look at what the guest changed.
+#[derive(Clone, Default)]
+pub struct Dir(Rc<RefCell<DirInner>>);
+
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for Dir {}
+unsafe impl Sync for Dir {}
+
+#[derive(Default)]
+struct DirInner {
+    entries: BTreeMap<String, Node>,
+    times: Times,
+    read_only: bool,
+}
+
+/// A regular file in an in-memory filesystem. Clones refer to the same file.
+#[derive(Clone, Default)]
+pub struct File(Rc<RefCell<FileInner>>);
+
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for File {}
+unsafe impl Sync for File {}
+
+#[derive(Default)]
+struct FileInner {
+    contents: Vec<u8>,
+    times: Times,
+    read_only: bool,
+}
+
+#[derive(Clone)]
+pub enum Node {
+    Dir(Dir),
+    File(File),
+}
+
+impl Node {
+    pub fn times(&self) -> Times {
+        match self {
+            Node::Dir(d) => d.times(),
+            Node::File(f) => f.times(),
+        }
+    }
+    /// Whether the guest is denied changes to this node. See
+    /// `Dir::set_read_only`.
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            Node::Dir(d) => d.0.borrow().read_only,
+            Node::File(f) => f.0.borrow().read_only,
+        }
+    }
+    pub fn is_same(&self, other: &Node) -> bool {
+        match (self, other) {
+            (Node::Dir(a), Node::Dir(b)) => Rc::ptr_eq(&a.0, &b.0),
+            (Node::File(a), Node::File(b)) => Rc::ptr_eq(&a.0, &b.0),
+            _ => false,
+        }
+    }
+    /// Identifies this node for as long as it exists.
+    pub(crate) fn id(&self) -> u64 {
+        match self {
+            Node::Dir(d) => Rc::as_ptr(&d.0) as *const u8 as usize as u64,
+            Node::File(f) => Rc::as_ptr(&f.0) as *const u8 as usize as u64,
+        }
+    }
+    fn touch(&self, now: u64) {
+        match self {
+            Node::Dir(d) => d.0.borrow_mut().times.changed = now,
+            Node::File(f) => f.0.borrow_mut().times.changed = now,
+        }
+    }
+    pub(crate) fn set_times(&self, times: Times) {
+        match self {
+            Node::Dir(d) => d.0.borrow_mut().times = times,
+            Node::File(f) => f.0.borrow_mut().times = times,
+        }
+    }
+}
+
+impl Dir {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Build a directory tree from a list of file paths and their contents.
+    pub fn from_files<P: AsRef<str>, C: Into<Vec<u8>>>(
+        files: impl IntoIterator<Item = (P, C)>,
+    ) -> Result<Self, ErrorCode> {
+        let dir = Self::new();
+        for (path, contents) in files {
+            dir.insert_file(path.as_ref(), contents)?;
+        }
+        Ok(dir)
+    }
+    /// Build a directory tree from a tar archive. Regular files and
+    /// directories are kept; links and other entry types are skipped. Long
+    /// paths are taken from GNU `L` entries and pax `path` records.
+    pub fn from_tar(archive: &[u8]) -> anyhow::Result<Self> {
+        let dir = Self::new();
+        let mut offset = 0;
+        // Set by an `L` or pax entry, for the entry after it.
+        let mut long_path: Option<String> = None;
+        let mut long_size: Option<usize> = None;
+        while offset < archive.len() {
+            let header = archive
+                .get(offset..offset + 512)
+                .ok_or_else(|| anyhow!("tar header at {offset} is truncated"))?;
+            if header.iter().all(|b| *b == 0) {
+                break;
+            }
+            let name = tar_field(&header[0..100])?;
+            let prefix = match header[257..262] == *b"ustar" {
+                true => tar_field(&header[345..500])?,
+                false => "",
+            };
+            let mut path = match prefix {
+                "" => String::from(name),
+                prefix => format!("{prefix}/{name}"),
+            };
+            let mut size = tar_size(&header[124..136])?;
+            let entry_type = header[156];
+            // Entries that describe the next one keep what they set for it.
+            if !matches!(entry_type, b'L' | b'K' | b'x' | b'g') {
+                path = long_path.take().unwrap_or(path);
+                size = long_size.take().unwrap_or(size);
+            }
+            let path = path.trim_start_matches('/');
+            offset += 512;
+            let contents = offset
+                .checked_add(size)
+                .and_then(|end| archive.get(offset..end))
+                .ok_or_else(|| anyhow!("tar entry {path} is truncated"))?;
+            match entry_type {
+                b'0' | 0 => {
+                    dir.insert_file(path, contents)
+                        .map_err(|e| anyhow!("tar entry {path}: {e}"))?;
+                }
+                b'5' => {
+                    dir.insert_dir(path)
+                        .map_err(|e| anyhow!("tar entry {path}: {e}"))?;
+                }
+                b'L' => long_path = Some(String::from(tar_field(contents)?)),
+                b'x' => {
+                    for (key, value) in pax_records(contents)? {
+                        match key {
+                            "path" => long_path = Some(String::from(value)),
+                            "size" => {
+                                long_size = Some(
+                                    value
+                                        .parse()
+                                        .map_err(|_| anyhow!("bad pax size {value:?}"))?,
+                                )
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                b'g' => {
+                    if pax_records(contents)?
+                        .iter()
+                        .any(|(key, _)| matches!(*key, "path" | "size"))
+                    {
+                        bail!("pax global headers setting path or size are not supported");
+                    }
+                }
+                // `K` names the target of a link, and links are skipped.
+                _ => {}
+            }
+            offset += size.div_ceil(512) * 512;
+        }
+        Ok(dir)
+    }
+    fn at(now: u64) -> Self {
+        let dir = Self::default();
+        dir.0.borrow_mut().times = Times::at(now);
+        dir
+    }
+    pub fn times(&self) -> Times {
+        self.0.borrow().times
+    }
+
+    /// Create a file at `path`, along with any missing parent directories,
+    /// replacing any file already there.
+    pub fn insert_file(&self, path: &str, contents: impl Into<Vec<u8>>) -> Result<File, ErrorCode> {
+        let (parent, name) = self.parent_of(path, true)?;
+        if name == "." || matches!(parent.child(&name), Some(Node::Dir(_))) {
+            return Err(ErrorCode::IsDirectory);
+        }
+        let file = File::default();
+        file.0.borrow_mut().contents = contents.into();
+        parent.insert(name, Node::File(file.clone()), 0);
+        Ok(file)
+    }
+    /// Create a directory at `path`, along with any missing parents. If the
+    /// directory already exists, it is returned as is.
+    pub fn insert_dir(&self, path: &str) -> Result<Dir, ErrorCode> {
+        let (parent, name) = self.parent_of(path, true)?;
+        match name.as_str() {
+            "." => Ok(parent),
+            _ => parent.mkdir(&name),
+        }
+    }
+    pub fn get(&self, path: &str) -> Option<Node> {
+        self.lookup(path).ok()
+    }
+    pub fn read_file(&self, path: &str) -> Option<Vec<u8>> {
+        match self.get(path)? {
+            Node::File(f) => Some(f.contents()),
+            Node::Dir(_) => None,
+        }
+    }
+    /// The names and nodes in this directory, sorted by name.
+    pub fn entries(&self) -> Vec<(String, Node)> {
+        self.0
+            .borrow()
+            .entries
+            .iter()
+            .map(|(name, node)| (name.clone(), node.clone()))
+            .collect()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().entries.is_empty()
+    }
+    pub fn is_read_only(&self) -> bool {
+        self.0.borrow().read_only
+    }
+
+    /// Deny or allow the guest changes to the node at `path`. The guest gets
+    /// `error-code.access` when it tries to write to a read-only file, or to
+    /// add, remove or rename entries in a read-only directory. To deny
+    /// changes to a whole tree, use `InstanceBuilder::preopen_read_only`
+    /// instead.
+    pub fn set_read_only(&self, path: &str, read_only: bool) -> Result<(), ErrorCode> {
+        match self.lookup(path)? {
+            Node::Dir(d) => d.0.borrow_mut().read_only = read_only,
+            Node::File(f) => f.0.borrow_mut().read_only = read_only,
+        }
+        Ok(())
+    }
+
+    fn mkdir(&self, name: &str) -> Result<Dir, ErrorCode> {
+        match self.child(name) {
+            Some(Node::Dir(d)) => Ok(d),
+            Some(Node::File(_)) => Err(ErrorCode::NotDirectory),
+            None => {
+                let dir = Dir::new();
+                self.insert(name.to_string(), Node::Dir(dir.clone()), 0);
+                Ok(dir)
+            }
+        }
+    }
+
+    pub(crate) fn child(&self, name: &str) -> Option<Node> {
+        self.0.borrow().entries.get(name).cloned()
+    }
+    pub(crate) fn insert(&self, name: String, node: Node, now: u64) {
+        node.touch(now);
+        let mut inner = self.0.borrow_mut();
+        inner.entries.insert(name, node);
+        inner.times.modify(now);
+    }
+    pub(crate) fn remove(&self, name: &str, now: u64) -> Option<Node> {
+        let mut inner = self.0.borrow_mut();
+        let node = inner.entries.remove(name)?;
+        inner.times.modify(now);
+        Some(node)
+    }
+    pub(crate) fn create_file(&self, name: String, now: u64) -> File {
+        let file = File::at(now);
+        self.insert(name, Node::File(file.clone()), now);
+        file
+    }
+    pub(crate) fn create_dir(&self, name: String, now: u64) -> Dir {
+        let dir = Dir::at(now);
+        self.insert(name, Node::Dir(dir.clone()), now);
+        dir
+    }
+    /// Whether `other` is this directory, or is inside of it.
+    pub(crate) fn contains(&self, other: &Dir) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+            || self
+                .0
+                .borrow()
+                .entries
+                .values()
+                .any(|node| matches!(node, Node::Dir(d) if d.contains(other)))
+    }
+
+    /// Resolve a path relative to this directory. Absolute paths, and paths
+    /// that leave this directory with `..`, are not permitted.
+    pub(crate) fn lookup(&self, path: &str) -> Result<Node, ErrorCode> {
+        let (parent, name) = self.parent_of(path, false)?;
+        match name.as_str() {
+            "." => Ok(Node::Dir(parent)),
+            _ => parent.child(&name).ok_or(ErrorCode::NoEntry),
+        }
+    }
+    /// Resolve all but the last component of a path relative to this
+    /// directory, returning the directory it names and the last component.
+    /// The last component is `.` if the path names a directory itself.
+    pub(crate) fn parent_of(&self, path: &str, create: bool) -> Result<(Dir, String), ErrorCode> {
+        if path.starts_with('/') {
+            return Err(ErrorCode::NotPermitted);
+        }
+        let mut stack = alloc::vec![self.clone()];
+        let mut components = path
+            .split('/')
+            .filter(|c| !c.is_empty() && *c != ".")
+            .peekable();
+        while let Some(component) = components.next() {
+            if component == ".." {
+                if stack.len() == 1 {
+                    return Err(ErrorCode::NotPermitted);
+                }
+                stack.pop();
+                continue;
+            }
+            let current = stack.last().expect("stack is never empty");
+            if components.peek().is_none() {
+                return Ok((current.clone(), component.to_string()));
+            }
+            let next = match current.child(component) {
+                Some(Node::Dir(d)) => d,
+                Some(Node::File(_)) => return Err(ErrorCode::NotDirectory),
+                None if create => current.mkdir(component)?,
+                None => return Err(ErrorCode::NoEntry),
+            };
+            stack.push(next);
+        }
+        let current = stack.pop().expect("stack is never empty");
+        Ok((current, ".".to_string()))
+    }
+}
+
+impl File {
+    fn at(now: u64) -> Self {
+        let file = Self::default();
+        file.0.borrow_mut().times = Times::at(now);
+        file
+    }
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().contents.clone()
+    }
+    pub fn len(&self) -> u64 {
+        self.0.borrow().contents.len() as u64
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().contents.is_empty()
+    }
+    pub fn times(&self) -> Times {
+        self.0.borrow().times
+    }
+
+    /// Read up to `len` bytes at `offset`, and whether the end of the file
+    /// was reached.
+    pub(crate) fn read(&self, offset: u64, len: u64, now: u64) -> (Vec<u8>, bool) {
+        let mut inner = self.0.borrow_mut();
+        inner.times.accessed = now;
+        let size = inner.contents.len();
+        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(size);
+        let end = usize::try_from(len)
+            .map(|len| start.saturating_add(len))
+            .unwrap_or(usize::MAX)
+            .min(size);
+        (inner.contents[start..end].to_vec(), end == size)
+    }
+    /// Write `bytes` at `offset`, unless that would make the file larger
+    /// than `max_size`.
+    pub(crate) fn write(
+        &self,
+        offset: u64,
+        bytes: &[u8],
+        now: u64,
+        max_size: u64,
+    ) -> Result<(), ErrorCode> {
+        let end = offset
+            .checked_add(bytes.len() as u64)
+            .filter(|end| *end <= max_size)
+            .ok_or(ErrorCode::FileTooLarge)?;
+        let offset = usize::try_from(offset).map_err(|_| ErrorCode::FileTooLarge)?;
+        let end = usize::try_from(end).map_err(|_| ErrorCode::FileTooLarge)?;
+        let mut inner = self.0.borrow_mut();
+        if inner.read_only {
+            return Err(ErrorCode::Access);
+        }
+        if inner.contents.len() < end {
+            inner.contents.resize(end, 0);
+        }
+        inner.contents[offset..end].copy_from_slice(bytes);
+        inner.times.modify(now);
+        Ok(())
+    }
+    pub(crate) fn set_len(&self, len: u64, now: u64, max_size: u64) -> Result<(), ErrorCode> {
+        if len > max_size {
+            return Err(ErrorCode::FileTooLarge);
+        }
+        let len = usize::try_from(len).map_err(|_| ErrorCode::FileTooLarge)?;
+        let mut inner = self.0.borrow_mut();
+        if inner.read_only {
+            return Err(ErrorCode::Access);
+        }
+        inner.contents.resize(len, 0);
+        inner.times.modify(now);
+        Ok(())
+    }
+}
+
+fn tar_field(field: &[u8]) -> anyhow::Result<&str> {
+    let len = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).map_err(|_| anyhow!("tar header is not utf-8"))
+}
+
+/// The `length key=value\n` records of a pax extended header.
+fn pax_records(data: &[u8]) -> anyhow::Result<Vec<(&str, &str)>> {
+    let data = core::str::from_utf8(data).map_err(|_| anyhow!("pax header is not utf-8"))?;
+    let mut records = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let bad = || anyhow!("bad pax record in {data:?}");
+        let (len, _) = rest.split_once(' ').ok_or_else(bad)?;
+        let len: usize = len.parse().map_err(|_| bad())?;
+        let record = rest.get(..len).ok_or_else(bad)?;
+        rest = &rest[len..];
+        let (_, record) = record.split_once(' ').ok_or_else(bad)?;
+        let record = record.strip_suffix('\n').ok_or_else(bad)?;
+        let (key, value) = record.split_once('=').ok_or_else(bad)?;
+        records.push((key, value));
+    }
+    Ok(records)
+}
+
+fn tar_size(field: &[u8]) -> anyhow::Result<usize> {
+    match tar_field(field)?.trim() {
+        "" => Ok(0),
+        digits => {
+            usize::from_str_radix(digits, 8).map_err(|_| anyhow!("bad tar entry size {digits:?}"))
+        }
+    }
+}
+
+/// An input-stream over a file, starting at an offset. Reading past the end
+/// of the file reports the stream as closed.
+pub(crate) struct FileReader {
+    file: File,
+    offset: u64,
+    clock: Clock,
+}
+impl FileReader {
+    pub fn new(file: File, offset: u64, clock: Clock) -> Self {
+        Self {
+            file,
+            offset,
+            clock,
+        }
+    }
+}
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for FileReader {}
+unsafe impl Sync for FileReader {}
+
+#[wasmtime_wasi_io::async_trait]
+impl Pollable for FileReader {
+    async fn ready(&mut self) {}
+}
+impl InputStream for FileReader {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        let (bytes, eof) = self.file.read(self.offset, size as u64, self.clock.get());
+        if bytes.is_empty() && eof && size > 0 {
+            return Err(StreamError::Closed);
+        }
+        self.offset += bytes.len() as u64;
+        Ok(Bytes::from(bytes))
+    }
+}
+
+/// An output-stream into a file, either at an offset or appending to the end
+/// of the file. Writes are recorded as modifications of `path`, and may not
+/// make the file larger than `max_size`.
+pub(crate) struct FileWriter {
+    file: File,
+    offset: Option<u64>,
+    max_size: u64,
+    clock: Clock,
+    changes: ChangeLog,
+    path: String,
+}
+impl FileWriter {
+    pub fn new(
+        file: File,
+        offset: Option<u64>,
+        max_size: u64,
+        clock: Clock,
+        changes: ChangeLog,
+        path: String,
+    ) -> Self {
+        Self {
+            file,
+            offset,
+            max_size,
+            clock,
+            changes,
+            path,
+        }
+    }
+}
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for FileWriter {}
+unsafe impl Sync for FileWriter {}
+
+#[wasmtime_wasi_io::async_trait]
+impl Pollable for FileWriter {
+    async fn ready(&mut self) {}
+}
+impl OutputStream for FileWriter {
+    fn check_write(&mut self) -> StreamResult<usize> {
+        let offset = self.offset.unwrap_or_else(|| self.file.len());
+        match self.max_size.checked_sub(offset) {
+            Some(room) if room > 0 => Ok(usize::try_from(room).unwrap_or(usize::MAX)),
+            _ => Err(StreamError::LastOperationFailed(anyhow::Error::msg(
+                ErrorCode::FileTooLarge,
+            ))),
+        }
+    }
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        let offset = self.offset.unwrap_or_else(|| self.file.len());
+        let now = self.clock.get();
+        self.file
+            .write(offset, &bytes, now, self.max_size)
+            .map_err(|code| StreamError::LastOperationFailed(anyhow::Error::msg(code)))?;
+        let path = self.path.clone();
+        self.changes.record(now, ChangeKind::Modified { path });
+        if let Some(offset) = &mut self.offset {
+            *offset += bytes.len() as u64;
+        }
+        Ok(())
+    }
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    // A 512-byte tar header. `prefix` is only written, with the ustar magic,
+    // when it is not empty.
+    fn header(name: &str, size: usize, kind: u8, prefix: &str) -> Vec<u8> {
+        let mut header = vec![0; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        let size = format!("{size:011o}");
+        header[124..135].copy_from_slice(size.as_bytes());
+        header[156] = kind;
+        if !prefix.is_empty() {
+            header[257..263].copy_from_slice(b"ustar\0");
+            header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        }
+        header
+    }
+
+    // A header followed by its contents, padded to a whole block.
+    fn entry(name: &str, kind: u8, contents: &[u8]) -> Vec<u8> {
+        let mut entry = header(name, contents.len(), kind, "");
+        entry.extend_from_slice(contents);
+        entry.resize(entry.len().div_ceil(512) * 512, 0);
+        entry
+    }
+
+    // One pax record, whose length counts itself.
+    fn pax(key: &str, value: &str) -> String {
+        let rest = key.len() + value.len() + 3;
+        let mut len = rest + 1;
+        while len.to_string().len() + rest != len {
+            len += 1;
+        }
+        format!("{len} {key}={value}\n")
+    }
+
+    #[test]
+    fn tar_joins_ustar_prefix_and_name() {
+        let mut archive = header("file.txt", 2, b'0', "some/dir");
+        archive.extend_from_slice(b"hi");
+        archive.resize(1024, 0);
+        let dir = Dir::from_tar(&archive).unwrap();
+        assert_eq!(dir.read_file("some/dir/file.txt"), Some(b"hi".to_vec()));
+    }
+
+    #[test]
+    fn tar_long_name_applies_to_next_entry_only() {
+        let long = "a/".repeat(60) + "file.txt";
+        let mut archive = entry("././@LongLink", b'L', format!("{long}\0").as_bytes());
+        archive.extend(entry("truncated", b'0', b"long"));
+        archive.extend(entry("short.txt", b'0', b"short"));
+        let dir = Dir::from_tar(&archive).unwrap();
+        assert_eq!(dir.read_file(&long), Some(b"long".to_vec()));
+        assert_eq!(dir.read_file("short.txt"), Some(b"short".to_vec()));
+        assert!(dir.get("truncated").is_none());
+    }
+
+    #[test]
+    fn tar_pax_overrides_path_and_size() {
+        let records = pax("path", "renamed.txt") + &pax("size", "3");
+        let mut archive = entry("PaxHeaders/x", b'x', records.as_bytes());
+        // The header's own size is ignored in favour of the pax size.
+        archive.extend(header("original.txt", 0, b'0', ""));
+        archive.extend_from_slice(b"abc");
+        archive.resize(archive.len().div_ceil(512) * 512, 0);
+        let dir = Dir::from_tar(&archive).unwrap();
+        assert_eq!(dir.read_file("renamed.txt"), Some(b"abc".to_vec()));
+        assert!(dir.get("original.txt").is_none());
+    }
+
+    #[test]
+    fn tar_rejects_malformed_pax_records() {
+        for records in ["99 path=a\n", "x path=a\n", "8 path=a\n", "10 path a\n"] {
+            let mut archive = entry("PaxHeaders/x", b'x', records.as_bytes());
+            archive.extend(entry("file.txt", b'0', b"a"));
+            assert!(Dir::from_tar(&archive).is_err(), "{records:?}");
+        }
+    }
+
+    #[test]
+    fn tar_rejects_truncated_entries_and_headers() {
+        let mut entry_cut_short = header("file.txt", 10, b'0', "");
+        entry_cut_short.extend_from_slice(b"short");
+        assert!(Dir::from_tar(&entry_cut_short).is_err());
+
+        let header_cut_short = &header("file.txt", 0, b'0', "")[..300];
+        assert!(Dir::from_tar(header_cut_short).is_err());
+    }
+
+    #[test]
+    fn tar_rejects_global_path() {
+        let mut archive = entry("pax_global", b'g', pax("path", "everything").as_bytes());
+        archive.extend(entry("file.txt", b'0', b"a"));
+        assert!(Dir::from_tar(&archive).is_err());
+
+        let mut archive = entry("pax_global", b'g', pax("comment", "ok").as_bytes());
+        archive.extend(entry("file.txt", b'0', b"a"));
+        let dir = Dir::from_tar(&archive).unwrap();
+        assert_eq!(dir.read_file("file.txt"), Some(b"a".to_vec()));
+    }
+}
@@ -1,10 +1,24 @@
+pub mod mock;
+pub mod router;
+
 pub use crate::bindings::wasi::http::types::{
     ErrorCode, FieldName, FieldValue, HeaderError, Method, Scheme, StatusCode,
 };
+use crate::clock::{Clock, Deadline, Timer};
+use crate::runtime::Executor;
+use crate::streams::{ChunkFeed, TimestampedWrites};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
 use alloc::string::String;
 use alloc::vec::Vec;
+use bytes::Bytes;
 use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
 use core::time::Duration;
+use futures_lite::future;
+use wasmtime_wasi_io::poll::Pollable;
+use wasmtime_wasi_io::streams::{DynInputStream, InputStream, StreamError, StreamResult};
 
 // Placeholder fields. This will contain pointers to some external resource
 // and the methods will retrieve these values out of there.
@@ -124,14 +138,66 @@ impl OutgoingRequest {
 
     pub async fn send(
         self,
-        _headers: ImmutFields,
-        _body: Option<OutgoingBody>,
-        _options: Option<RequestOptions>,
+        handler: &dyn OutgoingHandler,
+        timer: Timer,
+        headers: ImmutFields,
+        body: OutgoingBody,
+        options: RequestOptions,
     ) -> Result<(IncomingResponse, Fields, IncomingBody), ErrorCode> {
-        todo!()
+        // There is no separate connection phase visible to the embedding, so
+        // the connect and first byte timeouts are both measured from when the
+        // request is sent.
+        let connect = expire(
+            options.connect_timeout.map(|t| timer.after(t)),
+            ErrorCode::ConnectionTimeout,
+        );
+        let first_byte = expire(
+            options.first_byte_timeout.map(|t| timer.after(t)),
+            ErrorCode::ConnectionReadTimeout,
+        );
+        let response = handler.handle(timer.clone(), self, headers, body, options);
+        let (resp, headers, mut body) =
+            future::or(response, future::or(connect, first_byte)).await?;
+        if let Some(timeout) = options.between_bytes_timeout {
+            body.set_between_bytes_timeout(timer, timeout);
+        }
+        Ok((resp, headers, body))
     }
 }
 
+async fn expire<T>(deadline: Option<Deadline>, code: ErrorCode) -> Result<T, ErrorCode> {
+    match deadline {
+        Some(deadline) => {
+            deadline.await;
+            Err(code)
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Implemented by the embedder to send the requests a guest makes with
+/// `wasi:http/outgoing-handler.handle`.
+///
+/// The request's method, scheme, authority and path are available from the
+/// `OutgoingRequest`. The guest may still be writing the body when `handle`
+/// is called, in which case `OutgoingBody::state` is `Open` until it
+/// finishes or drops the body; `OutgoingBody::closed` waits for that. A body
+/// the guest never took is already `Finished`, and empty.
+/// The future returned is run on the embedding's executor, and its result is
+/// delivered to the guest's `future-incoming-response`. Use the `Timer` to
+/// wait on the calling instance's virtual clock.
+#[wasmtime_wasi_io::async_trait]
+pub trait OutgoingHandler: Send + Sync {
+    async fn handle(
+        &self,
+        timer: Timer,
+        request: OutgoingRequest,
+        headers: ImmutFields,
+        body: OutgoingBody,
+        options: RequestOptions,
+    ) -> Result<(IncomingResponse, Fields, IncomingBody), ErrorCode>;
+}
+
 // Minimum viable implementation
 #[derive(Debug)]
 pub struct Fields {
@@ -209,24 +275,215 @@ impl ImmutFields {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, Copy)]
 pub struct RequestOptions {
     pub connect_timeout: Option<Duration>,
     pub first_byte_timeout: Option<Duration>,
     pub between_bytes_timeout: Option<Duration>,
 }
 
-// putting off figuring out bodies for later
-pub struct IncomingBody {}
-pub struct OutgoingBody {}
+impl RequestOptions {
+    /// Fill in any timeout not set here from `defaults`.
+    pub fn or(self, defaults: RequestOptions) -> Self {
+        RequestOptions {
+            connect_timeout: self.connect_timeout.or(defaults.connect_timeout),
+            first_byte_timeout: self.first_byte_timeout.or(defaults.first_byte_timeout),
+            between_bytes_timeout: self
+                .between_bytes_timeout
+                .or(defaults.between_bytes_timeout),
+        }
+    }
+}
+
+/// The body of an incoming request or response. Its contents come from the
+/// host, either given in full up front or pushed in chunks as they arrive.
+pub struct IncomingBody {
+    feed: ChunkFeed,
+    between_bytes: Option<BetweenBytes>,
+}
+impl IncomingBody {
+    /// A body with no contents.
+    pub fn empty() -> Self {
+        Self::full(Bytes::new())
+    }
+    /// A body whose entire contents are known up front.
+    pub fn full(contents: impl Into<Bytes>) -> Self {
+        IncomingBody {
+            feed: ChunkFeed::full(contents.into()),
+            between_bytes: None,
+        }
+    }
+    /// A body whose contents will be pushed later. The guest's reads will
+    /// wait for more data until `finish` is called.
+    pub fn streaming() -> Self {
+        IncomingBody {
+            feed: ChunkFeed::new(),
+            between_bytes: None,
+        }
+    }
+    pub fn push(&self, chunk: impl Into<Bytes>) -> anyhow::Result<()> {
+        self.feed.push(chunk.into())
+    }
+    pub fn finish(&self) {
+        self.feed.close()
+    }
+    pub(crate) fn feed(&self) -> ChunkFeed {
+        self.feed.clone()
+    }
+    /// Fail reads with `ErrorCode::ConnectionReadTimeout` if no new data
+    /// arrives within `timeout` of the previous chunk, or of this call.
+    pub(crate) fn set_between_bytes_timeout(&mut self, timer: Timer, timeout: Duration) {
+        self.between_bytes = Some(BetweenBytes {
+            feed: self.feed.clone(),
+            deadline: timer.after(timeout),
+            timer,
+            timeout,
+        });
+    }
+    pub(crate) fn into_stream(self, executor: &Executor) -> DynInputStream {
+        self.feed.watch(executor, "incoming-body stream");
+        match self.between_bytes {
+            Some(between_bytes) => Box::new(between_bytes),
+            None => Box::new(self.feed),
+        }
+    }
+}
+
+struct BetweenBytes {
+    feed: ChunkFeed,
+    timer: Timer,
+    timeout: Duration,
+    deadline: Deadline,
+}
+
+#[wasmtime_wasi_io::async_trait]
+impl Pollable for BetweenBytes {
+    async fn ready(&mut self) {
+        let Self { feed, deadline, .. } = self;
+        future::or(feed.ready(), deadline.clone()).await
+    }
+}
+impl InputStream for BetweenBytes {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        let bytes = self.feed.read(size)?;
+        if !bytes.is_empty() {
+            self.deadline = self.timer.after(self.timeout);
+        } else if self.timer.now() >= self.deadline.due() {
+            return Err(StreamError::LastOperationFailed(anyhow::Error::msg(
+                ErrorCode::ConnectionReadTimeout,
+            )));
+        }
+        Ok(bytes)
+    }
+}
+
+/// The body of an outgoing response or request. Each write made by the
+/// guest is recorded along with the Clock time it was made at.
+#[derive(Clone)]
+pub struct OutgoingBody {
+    writes: TimestampedWrites,
+    state: Rc<Cell<BodyState>>,
+    waiters: Rc<RefCell<Vec<Waker>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyState {
+    /// The guest has not yet finished or dropped the body. A body the guest
+    /// never retrieved is `Finished` and empty instead, once the request is
+    /// sent or the response is set.
+    Open,
+    /// The guest called `outgoing-body.finish`.
+    Finished,
+    /// The guest dropped the body without calling `outgoing-body.finish`.
+    Dropped,
+}
+
+impl OutgoingBody {
+    pub(crate) fn new(clock: Clock) -> Self {
+        OutgoingBody {
+            writes: TimestampedWrites::new(clock),
+            state: Rc::new(Cell::new(BodyState::Open)),
+            waiters: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+    /// Each chunk written so far, with the time it was written.
+    pub fn chunks(&self) -> Vec<(u64, Bytes)> {
+        self.writes.chunks()
+    }
+    /// All bytes written so far, concatenated.
+    pub fn contents(&self) -> Vec<u8> {
+        self.chunks()
+            .into_iter()
+            .flat_map(|(_, chunk)| chunk)
+            .collect()
+    }
+    pub fn state(&self) -> BodyState {
+        self.state.get()
+    }
+    /// Wait until the guest finishes or drops the body, and return which it
+    /// did.
+    pub async fn closed(&self) -> BodyState {
+        poll_fn(|cx| match self.state.get() {
+            BodyState::Open => {
+                let mut waiters = self.waiters.borrow_mut();
+                if !waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                    waiters.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+            state => Poll::Ready(state),
+        })
+        .await
+    }
+    pub(crate) fn finish(&self) {
+        self.close(BodyState::Finished)
+    }
+    pub(crate) fn drop_early(&self) {
+        self.close(BodyState::Dropped)
+    }
+    fn close(&self, state: BodyState) {
+        if self.state.get() == BodyState::Open {
+            self.state.set(state);
+            for waker in self.waiters.borrow_mut().drain(..) {
+                waker.wake()
+            }
+        }
+    }
+}
+// SAFETY: single threaded
+unsafe impl Send for OutgoingBody {}
+unsafe impl Sync for OutgoingBody {}
+
+#[wasmtime_wasi_io::async_trait]
+impl wasmtime_wasi_io::poll::Pollable for OutgoingBody {
+    async fn ready(&mut self) {}
+}
+impl wasmtime_wasi_io::streams::OutputStream for OutgoingBody {
+    fn check_write(&mut self) -> wasmtime_wasi_io::streams::StreamResult<usize> {
+        match self.state() {
+            BodyState::Open => self.writes.check_write(),
+            _ => Err(wasmtime_wasi_io::streams::StreamError::Closed),
+        }
+    }
+    fn write(&mut self, contents: Bytes) -> wasmtime_wasi_io::streams::StreamResult<()> {
+        match self.state() {
+            BodyState::Open => self.writes.write(contents),
+            _ => Err(wasmtime_wasi_io::streams::StreamError::Closed),
+        }
+    }
+    fn flush(&mut self) -> wasmtime_wasi_io::streams::StreamResult<()> {
+        self.writes.flush()
+    }
+}
+
+/// Everything a guest sends to its response-outparam on success.
+pub type Response = (OutgoingResponse, ImmutFields, OutgoingBody);
 
 // This will contain some pointers that know where to write an outgoing response into the
 // embedding???
 #[derive(Clone)]
 pub struct ResponseOutparam {
-    mailbox: alloc::rc::Rc<
-        core::cell::RefCell<Option<Result<(OutgoingResponse, ImmutFields), ErrorCode>>>,
-    >,
+    mailbox: alloc::rc::Rc<core::cell::RefCell<Option<Result<Response, ErrorCode>>>>,
 }
 // SAFETY: single threaded
 unsafe impl Send for ResponseOutparam {}
@@ -237,18 +494,13 @@ impl ResponseOutparam {
             mailbox: alloc::rc::Rc::new(core::cell::RefCell::new(None)),
         }
     }
-    pub fn send_success(
-        self,
-        resp: OutgoingResponse,
-        headers: ImmutFields,
-        _body: Option<OutgoingBody>,
-    ) {
-        *self.mailbox.borrow_mut() = Some(Ok((resp, headers)));
+    pub fn send_success(self, resp: OutgoingResponse, headers: ImmutFields, body: OutgoingBody) {
+        *self.mailbox.borrow_mut() = Some(Ok((resp, headers, body)));
     }
     pub fn send_error(self, err: ErrorCode) {
         *self.mailbox.borrow_mut() = Some(Err(err));
     }
-    pub fn into_inner(self) -> anyhow::Result<(OutgoingResponse, ImmutFields)> {
+    pub fn into_inner(self) -> anyhow::Result<Response> {
         Ok(self
             .mailbox
             .borrow_mut()
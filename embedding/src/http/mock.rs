@@ -0,0 +1,179 @@
+//! A scripted `OutgoingHandler` for tests. Requests are matched by method,
+//! authority and path, and answered with a canned response after a delay
+//! measured on the calling instance's virtual clock.
+
+use super::{
+    ErrorCode, Fields, ImmutFields, IncomingBody, IncomingResponse, Method, OutgoingBody,
+    OutgoingHandler, OutgoingRequest, RequestOptions, Scheme, StatusCode,
+};
+use crate::clock::Timer;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bytes::Bytes;
+use core::cell::RefCell;
+use core::time::Duration;
+
+/// The response a `MockBackend` gives to a matching request.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status_code: StatusCode,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+    delay: Duration,
+}
+
+impl MockResponse {
+    pub fn new(status_code: StatusCode) -> Self {
+        MockResponse {
+            status_code,
+            headers: Vec::new(),
+            body: Bytes::new(),
+            delay: Duration::ZERO,
+        }
+    }
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+    /// How long after the request is made the response arrives.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+/// A request received by a `MockBackend`, kept for later assertions.
+#[derive(Clone)]
+pub struct RecordedRequest {
+    /// Clock time at which the guest made the request.
+    pub time: u64,
+    pub method: Method,
+    pub scheme: Option<Scheme>,
+    pub authority: Option<String>,
+    pub path_with_query: Option<String>,
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// The guest may still be writing the body when the request is recorded.
+    pub body: OutgoingBody,
+}
+
+struct Route {
+    method: Method,
+    authority: String,
+    path: String,
+    response: MockResponse,
+}
+
+#[derive(Default)]
+struct MockInner {
+    routes: Vec<Route>,
+    requests: Vec<RecordedRequest>,
+}
+
+/// An `OutgoingHandler` that answers from a script of canned responses.
+/// Clones share the same script and request log, so keep a clone to inspect
+/// the requests after registering the backend with the embedding.
+///
+/// Requests that match no route fail with `ErrorCode::DestinationNotFound`.
+#[derive(Clone, Default)]
+pub struct MockBackend(Rc<RefCell<MockInner>>);
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for MockBackend {}
+unsafe impl Sync for MockBackend {}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Respond to requests with this method, authority and path (ignoring any
+    /// query) with `response`. The first matching route wins.
+    pub fn route(
+        &self,
+        method: Method,
+        authority: impl Into<String>,
+        path: impl Into<String>,
+        response: MockResponse,
+    ) {
+        self.0.borrow_mut().routes.push(Route {
+            method,
+            authority: authority.into(),
+            path: path.into(),
+            response,
+        });
+    }
+    /// Every request received so far, in the order they were made.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.0.borrow().requests.clone()
+    }
+
+    fn lookup(&self, request: &OutgoingRequest) -> Option<MockResponse> {
+        let method = request.method();
+        let authority = request.authority().unwrap_or_default();
+        let path_with_query = request.path_with_query().unwrap_or_default();
+        let path = path_with_query
+            .split_once('?')
+            .map(|(path, _)| path)
+            .unwrap_or(&path_with_query);
+        self.0
+            .borrow()
+            .routes
+            .iter()
+            .find(|route| {
+                same_method(&route.method, &method)
+                    && route.authority == authority
+                    && route.path == path
+            })
+            .map(|route| route.response.clone())
+    }
+}
+
+fn same_method(a: &Method, b: &Method) -> bool {
+    match (a, b) {
+        (Method::Other(a), Method::Other(b)) => a == b,
+        _ => core::mem::discriminant(a) == core::mem::discriminant(b),
+    }
+}
+
+#[wasmtime_wasi_io::async_trait]
+impl OutgoingHandler for MockBackend {
+    async fn handle(
+        &self,
+        timer: Timer,
+        request: OutgoingRequest,
+        headers: ImmutFields,
+        body: OutgoingBody,
+        _options: RequestOptions,
+    ) -> Result<(IncomingResponse, Fields, IncomingBody), ErrorCode> {
+        let response = self.lookup(&request);
+        self.0.borrow_mut().requests.push(RecordedRequest {
+            time: timer.now(),
+            method: request.method(),
+            scheme: request.scheme(),
+            authority: request.authority(),
+            path_with_query: request.path_with_query(),
+            headers: headers.entries(),
+            body,
+        });
+
+        let response = response.ok_or(ErrorCode::DestinationNotFound)?;
+        timer.after(response.delay).await;
+
+        let fields = Fields::new();
+        for (name, value) in response.headers {
+            fields
+                .insert(name, value.into_bytes())
+                .map_err(|_| ErrorCode::InternalError(Some("invalid mock header".into())))?;
+        }
+        Ok((
+            IncomingResponse {
+                status_code: response.status_code,
+            },
+            fields,
+            IncomingBody::full(response.body),
+        ))
+    }
+}
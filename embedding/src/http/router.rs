@@ -0,0 +1,182 @@
+//! An `OutgoingHandler` that serves requests with other components, so that
+//! guests can call each other over HTTP without any network. Each request is
+//! handled by a new instance of the component routed to its authority, run
+//! on the same `Scheduler` as the caller.
+//!
+//! The routed components usually have the router as their own outgoing
+//! handler, so that any of them can call any other. To avoid a reference
+//! cycle through them, the runtime is given a `RouterHandle`, which does not
+//! keep the routes alive: register `Router::handler` with
+//! `Runtime::set_outgoing_handler`, and keep the `Router` itself for as long
+//! as requests should be served.
+
+use super::{
+    BodyState, ErrorCode, Fields, ImmutFields, IncomingBody, IncomingRequest, IncomingResponse,
+    OutgoingBody, OutgoingHandler, OutgoingRequest, RequestOptions,
+};
+use crate::clock::Timer;
+use crate::{Completion, RunnableComponent, Scheduler};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::rc::{Rc, Weak};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// Routes outgoing requests by authority to the `incoming-handler` of a
+/// component. Requests to an authority with no route fail with
+/// `ErrorCode::DestinationNotFound`.
+///
+/// Instances handling requests are created on the router's scheduler. Create
+/// the calling instances on that scheduler too, so the whole topology is
+/// stepped and clocked together.
+#[derive(Clone)]
+pub struct Router {
+    scheduler: Scheduler,
+    inner: Rc<RouterInner>,
+}
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for Router {}
+unsafe impl Sync for Router {}
+
+#[derive(Default)]
+struct RouterInner {
+    routes: RefCell<BTreeMap<String, RunnableComponent>>,
+    reports: RefCell<Vec<(String, String)>>,
+}
+
+impl Router {
+    pub fn new(scheduler: &Scheduler) -> Self {
+        Router {
+            scheduler: scheduler.clone(),
+            inner: Rc::default(),
+        }
+    }
+    /// Handle requests to `authority` with new instances of `component`,
+    /// replacing any earlier route for it.
+    pub fn route(&self, authority: impl Into<String>, component: &RunnableComponent) {
+        self.inner
+            .routes
+            .borrow_mut()
+            .insert(authority.into(), component.clone());
+    }
+    /// The authority and report of each instance that has finished handling
+    /// a request, in the order they finished.
+    pub fn reports(&self) -> Vec<(String, String)> {
+        self.inner.reports.borrow().clone()
+    }
+    /// An `OutgoingHandler` that serves requests with this router's routes
+    /// while the router is alive. Once every clone of the router is dropped,
+    /// its requests fail with `ErrorCode::DestinationNotFound`.
+    pub fn handler(&self) -> RouterHandle {
+        RouterHandle {
+            scheduler: self.scheduler.clone(),
+            inner: Rc::downgrade(&self.inner),
+        }
+    }
+}
+
+/// The `OutgoingHandler` for a `Router`, from `Router::handler`. It holds
+/// the router weakly, so components routed to can have it as their handler
+/// without keeping the router alive.
+#[derive(Clone)]
+pub struct RouterHandle {
+    scheduler: Scheduler,
+    inner: Weak<RouterInner>,
+}
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for RouterHandle {}
+unsafe impl Sync for RouterHandle {}
+
+#[wasmtime_wasi_io::async_trait]
+impl OutgoingHandler for RouterHandle {
+    async fn handle(
+        &self,
+        _timer: Timer,
+        request: OutgoingRequest,
+        headers: ImmutFields,
+        body: OutgoingBody,
+        _options: RequestOptions,
+    ) -> Result<(IncomingResponse, Fields, IncomingBody), ErrorCode> {
+        let authority = request.authority().unwrap_or_default();
+        let inner = self.inner.upgrade().ok_or(ErrorCode::DestinationNotFound)?;
+        let component = inner
+            .routes
+            .borrow()
+            .get(&authority)
+            .cloned()
+            .ok_or(ErrorCode::DestinationNotFound)?;
+        // Only the router's own clones keep it alive while this request is
+        // served.
+        drop(inner);
+
+        let incoming = IncomingRequest {
+            method: request.method(),
+            path_with_query: request.path_with_query(),
+            scheme: request.scheme(),
+            authority: request.authority(),
+        };
+        let fields = Fields::new();
+        for (name, value) in headers.entries() {
+            fields
+                .insert(name, value)
+                .map_err(|_| internal_error("invalid request header"))?;
+        }
+        // The callee sees the request body once the caller has finished writing it.
+        let executor = self.scheduler.executor();
+        let incoming_body = match executor
+            .wait_on("caller's request body", body.closed())
+            .await
+        {
+            BodyState::Finished => IncomingBody::full(body.contents()),
+            _ => return Err(ErrorCode::HttpProtocolError),
+        };
+
+        let running = component
+            .instance()
+            .scheduler(&self.scheduler)
+            .create(incoming, fields, incoming_body)
+            .map_err(|e| internal_error(&format!("{e:#}")))?;
+        let (report, res) = executor
+            .wait_on(format!("handler for {authority}"), running.completion())
+            .await;
+        if let Some(inner) = self.inner.upgrade() {
+            inner.reports.borrow_mut().push((authority, report));
+        }
+
+        match res {
+            Ok(Completion::Response((response, headers, body))) => {
+                // The handler has returned, so its body can no longer be
+                // finished. Anything but a finished body would arrive truncated.
+                if body.state() != BodyState::Finished {
+                    return Err(ErrorCode::HttpProtocolError);
+                }
+                let fields = Fields::new();
+                for (name, value) in headers.entries() {
+                    fields
+                        .insert(name, value)
+                        .map_err(|_| internal_error("invalid response header"))?;
+                }
+                Ok((
+                    IncomingResponse {
+                        status_code: response.status_code(),
+                    },
+                    fields,
+                    IncomingBody::full(body.contents()),
+                ))
+            }
+            Ok(Completion::Exited { code }) => Err(internal_error(&format!(
+                "handler exited without responding: {code}"
+            ))),
+            Ok(Completion::OutOfFuel) => Err(internal_error("handler ran out of fuel")),
+            Err(e) => match e.downcast_ref::<ErrorCode>() {
+                Some(code) => Err(code.clone()),
+                None => Err(internal_error(&format!("{e:#}"))),
+            },
+        }
+    }
+}
+
+fn internal_error(message: &str) -> ErrorCode {
+    ErrorCode::InternalError(Some(String::from(message)))
+}
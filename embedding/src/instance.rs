@@ -0,0 +1,286 @@
+use crate::bindings;
+use crate::ctx::{EmbeddingCtx, InstanceConfig};
+use crate::fs::{Dir, Preopen};
+use crate::output::{OutputLimit, OutputSink};
+use crate::streams::ChunkFeed;
+use crate::{exited, Completion, RunnableComponent, RunningComponent, Scheduler};
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use anyhow::{Context as _, Result};
+use bytes::Bytes;
+use core::time::Duration;
+use wasmtime::Store;
+
+/// How much fuel a guest consumes between yields to the executor, unless
+/// set with `InstanceBuilder::fuel_yield_interval`.
+pub const DEFAULT_FUEL_YIELD_INTERVAL: u64 = 10_000;
+
+/// The largest a guest may make a file in its preopened directories, in
+/// bytes, unless set with `InstanceBuilder::max_file_size`.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 64 << 20;
+
+/// Builds a single instance of a `RunnableComponent`. Each instance gets its
+/// own arguments, environment, working directory and random seed, so these
+/// can change between instances without loading the component again.
+pub struct InstanceBuilder<'a> {
+    component: &'a RunnableComponent,
+    scheduler: Option<Scheduler>,
+    config: InstanceConfig,
+}
+
+impl<'a> InstanceBuilder<'a> {
+    pub(crate) fn new(component: &'a RunnableComponent) -> Self {
+        InstanceBuilder {
+            component,
+            scheduler: None,
+            config: InstanceConfig {
+                outgoing_handler: component.outgoing_handler.clone(),
+                default_request_options: component.default_request_options,
+                ..InstanceConfig::default()
+            },
+        }
+    }
+
+    /// Append an argument, as returned by `wasi:cli/environment.get-arguments`.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.config.arguments.push(arg.into());
+        self
+    }
+    pub fn args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.config
+            .arguments
+            .extend(args.into_iter().map(Into::into));
+        self
+    }
+    /// Add an environment variable, as returned by
+    /// `wasi:cli/environment.get-environment`.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.environment.push((key.into(), value.into()));
+        self
+    }
+    pub fn envs(
+        mut self,
+        vars: impl IntoIterator<Item = (impl Into<String>, impl Into<String>)>,
+    ) -> Self {
+        self.config
+            .environment
+            .extend(vars.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+    /// Set the directory returned by `wasi:cli/environment.initial-cwd`.
+    pub fn cwd(mut self, cwd: impl Into<String>) -> Self {
+        self.config.initial_cwd = Some(cwd.into());
+        self
+    }
+
+    /// Seed the generator behind `wasi:random`. Instances given the same seed
+    /// see the same random bytes. The seed is 0 unless set.
+    pub fn random_seed(mut self, seed: u64) -> Self {
+        self.config.random_seed = seed;
+        self
+    }
+    /// Take the bytes for `wasi:random` from the embedder instead of the
+    /// seeded generator.
+    pub fn entropy(mut self, entropy: impl Fn(&mut [u8]) + Send + Sync + 'static) -> Self {
+        self.config.entropy = Some(Arc::new(entropy));
+        self
+    }
+
+    /// Set the time `wasi:clocks/wall-clock` reports when the virtual clock is
+    /// at 0, as a duration since the Unix epoch. The wall clock then advances
+    /// in step with the virtual clock. The epoch is the Unix epoch unless set.
+    pub fn wall_clock_epoch(mut self, epoch: Duration) -> Self {
+        self.config.wall_clock_epoch = epoch;
+        self
+    }
+
+    /// Make a directory available to the guest through
+    /// `wasi:filesystem/preopens.get-directories`, under the given name. The
+    /// guest's changes are visible through any other clone of the `Dir`.
+    pub fn preopen(mut self, name: impl Into<String>, dir: Dir) -> Self {
+        self.config.preopens.push(Preopen {
+            name: name.into(),
+            dir,
+            read_only: false,
+        });
+        self
+    }
+    /// Make a directory available to the guest like `preopen`, but deny the
+    /// guest any changes to it. Writes, and creating, removing or renaming
+    /// entries, fail with `error-code.read-only`.
+    pub fn preopen_read_only(mut self, name: impl Into<String>, dir: Dir) -> Self {
+        self.config.preopens.push(Preopen {
+            name: name.into(),
+            dir,
+            read_only: true,
+        });
+        self
+    }
+    /// The largest the guest may make a file, in bytes, by writing to it or
+    /// setting its size. Beyond this, writes fail with
+    /// `error-code.file-too-large`. Defaults to `DEFAULT_MAX_FILE_SIZE`.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.config.max_file_size = Some(bytes);
+        self
+    }
+
+    /// Give the guest's stdin the entire contents up front. After reading
+    /// them, the guest sees stdin closed. Stdin is empty unless set.
+    pub fn stdin(mut self, contents: impl Into<Bytes>) -> Self {
+        self.config.stdin = Some(ChunkFeed::full(contents.into()));
+        self
+    }
+    /// Leave the guest's stdin open, to be written with
+    /// `RunningComponent::push_stdin` and closed with
+    /// `RunningComponent::close_stdin`.
+    pub fn stdin_streaming(mut self) -> Self {
+        self.config.stdin = Some(ChunkFeed::new());
+        self
+    }
+
+    /// Pass each write to the guest's stdout and stderr to `sink` as it
+    /// happens. The output is still captured for `RunningComponent::output`
+    /// and the report.
+    pub fn output_sink(mut self, sink: impl OutputSink + 'static) -> Self {
+        self.config.output_sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Bound how much of the guest's stdout is kept. Unbounded unless set.
+    pub fn stdout_limit(mut self, limit: OutputLimit) -> Self {
+        self.config.stdout_limit = limit;
+        self
+    }
+    /// Bound how much of the guest's stderr is kept. Unbounded unless set.
+    pub fn stderr_limit(mut self, limit: OutputLimit) -> Self {
+        self.config.stderr_limit = limit;
+        self
+    }
+
+    /// Run the instance on a scheduler shared with other instances, instead
+    /// of on a scheduler of its own.
+    pub fn scheduler(mut self, scheduler: &Scheduler) -> Self {
+        self.scheduler = Some(scheduler.clone());
+        self
+    }
+
+    /// Limit the guest to this much fuel, which wasm instructions consume as
+    /// they execute. A guest that runs out completes with
+    /// `Completion::OutOfFuel`. Unlimited unless set.
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.config.fuel = Some(fuel);
+        self
+    }
+    /// Make the guest yield to the executor each time it consumes this much
+    /// fuel, so that one step runs a bounded amount of guest code. Defaults
+    /// to `DEFAULT_FUEL_YIELD_INTERVAL`.
+    pub fn fuel_yield_interval(mut self, interval: u64) -> Self {
+        self.config.fuel_yield_interval = Some(interval);
+        self
+    }
+
+    fn store(self) -> Result<(Scheduler, Store<EmbeddingCtx>)> {
+        let scheduler = self.scheduler.unwrap_or_default();
+        let fuel = self.config.fuel.unwrap_or(u64::MAX);
+        let interval = self
+            .config
+            .fuel_yield_interval
+            .unwrap_or(DEFAULT_FUEL_YIELD_INTERVAL);
+        let mut store = Store::new(
+            &self.component.engine,
+            EmbeddingCtx::new(
+                scheduler.executor().clone(),
+                scheduler.clock().clone(),
+                self.config,
+            ),
+        );
+        store.set_fuel(fuel)?;
+        store.fuel_async_yield_interval(Some(interval))?;
+        Ok((scheduler, store))
+    }
+
+    /// Run the component's `wasi:http/incoming-handler.handle` export on the
+    /// given request.
+    pub fn create(
+        self,
+        incoming: crate::http::IncomingRequest,
+        headers: crate::http::Fields,
+        body: crate::http::IncomingBody,
+    ) -> Result<RunningComponent> {
+        let bindings_pre = bindings::BindingsPre::new(self.component.instance_pre.clone())
+            .context("component does not export wasi:http/incoming-handler")?;
+        let (scheduler, mut store) = self.store()?;
+        let fs_changes = store.data().fs_changes().clone();
+        let stdin = store.data().stdin_feed().clone();
+        let captured = store.data().captured_output().clone();
+        let request_body = body.feed();
+        let mailbox = crate::http::ResponseOutparam::new();
+        let fut = async move {
+            let instance = match bindings_pre.instantiate_async(&mut store).await {
+                Ok(i) => i,
+                Err(e) => return (store.into_data(), Err(e).context("instantiating")),
+            };
+            match instance
+                .wasi_http_incoming_handler_handle(
+                    &mut store,
+                    incoming,
+                    headers,
+                    body,
+                    mailbox.clone(),
+                )
+                .await
+            {
+                Ok(()) => {}
+                Err(e) => return (store.into_data(), exited(e).context("running handler")),
+            };
+
+            (
+                store.into_data(),
+                mailbox.into_inner().map(Completion::Response),
+            )
+        };
+        let task = scheduler.executor().spawn("incoming-handler", fut);
+
+        Ok(RunningComponent {
+            scheduler,
+            request_body: Some(request_body),
+            stdin,
+            captured,
+            fs_changes,
+            output: Box::pin(task),
+        })
+    }
+
+    /// Run the component's `wasi:cli/run.run` export.
+    pub fn create_command(self) -> Result<RunningComponent> {
+        let command_pre = bindings::CommandPre::new(self.component.instance_pre.clone())
+            .context("component does not export wasi:cli/run")?;
+        let (scheduler, mut store) = self.store()?;
+        let fs_changes = store.data().fs_changes().clone();
+        let stdin = store.data().stdin_feed().clone();
+        let captured = store.data().captured_output().clone();
+        let fut = async move {
+            let command = match command_pre.instantiate_async(&mut store).await {
+                Ok(c) => c,
+                Err(e) => return (store.into_data(), Err(e).context("instantiating")),
+            };
+            let res = match command.wasi_cli_run().call_run(&mut store).await {
+                Ok(code) => Ok(Completion::Exited { code: code.into() }),
+                Err(e) => exited(e).context("running command"),
+            };
+            (store.into_data(), res)
+        };
+        let task = scheduler.executor().spawn("command", fut);
+
+        Ok(RunningComponent {
+            scheduler,
+            request_body: None,
+            stdin,
+            captured,
+            fs_changes,
+            output: Box::pin(task),
+        })
+    }
+}
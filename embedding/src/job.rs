@@ -19,6 +19,8 @@ use wasmtime_wasi_io::poll::Pollable;
 /// This mechanism can be used to implement the pseudo-futures frequently
 /// exposed in WASI 0.2 interfaces.
 pub struct Job<T> {
+    executor: Executor,
+    name: &'static str,
     task: Pin<Box<Task<T>>>,
     received: Option<T>,
     gone: bool,
@@ -45,9 +47,17 @@ impl<T> Job<T>
 where
     T: Send + 'static,
 {
-    pub fn spawn(executor: &Executor, f: impl Future<Output = T> + Send + 'static) -> Self {
-        let task = Box::pin(executor.spawn(f));
+    /// Spawn `f` as a task called `name`. Tasks waiting on the Job are noted
+    /// as waiting on `name` too.
+    pub fn spawn(
+        executor: &Executor,
+        name: &'static str,
+        f: impl Future<Output = T> + Send + 'static,
+    ) -> Self {
+        let task = Box::pin(executor.spawn(name, f));
         Self {
+            executor: executor.clone(),
+            name,
             task,
             received: None,
             gone: false,
@@ -101,6 +111,8 @@ where
     T: Send + 'static,
 {
     async fn ready(&mut self) {
-        poll_fn(|cx| self.poll(cx)).await
+        let executor = self.executor.clone();
+        let name = self.name;
+        executor.wait_on(name, poll_fn(|cx| self.poll(cx))).await
     }
 }
@@ -4,149 +4,345 @@ extern crate alloc;
 mod bindings;
 mod clock;
 mod ctx;
+mod driver;
+pub mod fs;
 pub mod http;
+mod instance;
 pub mod job;
 mod noop_waker;
+pub mod output;
+mod random;
 mod runtime;
+mod scheduler;
 mod streams;
 
-use clock::Clock;
+pub use clock::{Deadline, Timer};
+pub use driver::{HostTime, RealTimeDriver};
+pub use instance::{InstanceBuilder, DEFAULT_FUEL_YIELD_INTERVAL, DEFAULT_MAX_FILE_SIZE};
+pub use scheduler::{BlockedTask, HostSource, Scheduler, StepOutcome};
+
 use ctx::EmbeddingCtx;
-use runtime::Executor;
+use streams::ChunkFeed;
 
 use alloc::boxed::Box;
 use alloc::string::String;
-use anyhow::{Context as _, Result};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use anyhow::{anyhow, Context as _, Result};
 use async_task::Task;
+use bytes::Bytes;
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll};
 
-use wasmtime::component::{Component, Linker};
-use wasmtime::{Config, Engine, Store};
+use wasmtime::component::{Component, InstancePre, Linker};
+use wasmtime::{Config, Engine};
 
 pub struct Runtime {
     engine: Engine,
     linker: Linker<EmbeddingCtx>,
+    outgoing_handler: Option<Arc<dyn crate::http::OutgoingHandler>>,
+    default_request_options: crate::http::RequestOptions,
 }
 
 impl Runtime {
-    pub fn new() -> Result<Self> {
+    /// The engine configuration components must be compiled with to be
+    /// loaded by a `Runtime`.
+    pub fn config() -> Config {
         let mut config = Config::new();
         config.async_support(true);
-        let engine = Engine::new(&config)?;
+        config.consume_fuel(true);
+        config
+    }
+
+    pub fn new() -> Result<Self> {
+        let engine = Engine::new(&Self::config())?;
         let mut linker = Linker::new(&engine);
         wasmtime_wasi_io::add_to_linker_async(&mut linker)?;
         bindings::add_to_linker_async(&mut linker)?;
-        Ok(Runtime { engine, linker })
+        Ok(Runtime {
+            engine,
+            linker,
+            outgoing_handler: None,
+            default_request_options: crate::http::RequestOptions::default(),
+        })
+    }
+
+    /// Handle the outgoing HTTP requests made by every component loaded after
+    /// this call. Without a handler, guests' requests are denied.
+    pub fn set_outgoing_handler(&mut self, handler: impl crate::http::OutgoingHandler + 'static) {
+        self.outgoing_handler = Some(Arc::new(handler));
+    }
+
+    /// Timeouts applied to the outgoing HTTP requests of every component
+    /// loaded after this call, where the guest does not set its own.
+    pub fn set_default_request_options(&mut self, options: crate::http::RequestOptions) {
+        self.default_request_options = options;
     }
 
     pub fn load(&self, cwasm: &[u8]) -> Result<RunnableComponent> {
         let component = unsafe { Component::deserialize(&self.engine, cwasm)? };
         let instance_pre = self.linker.instantiate_pre(&component)?;
-        let bindings_pre = bindings::BindingsPre::new(instance_pre)?;
         Ok(RunnableComponent {
             engine: self.engine.clone(),
-            bindings_pre,
+            instance_pre,
+            outgoing_handler: self.outgoing_handler.clone(),
+            default_request_options: self.default_request_options,
         })
     }
 }
 
+#[derive(Clone)]
 pub struct RunnableComponent {
     engine: Engine,
-    bindings_pre: bindings::BindingsPre<EmbeddingCtx>,
+    instance_pre: InstancePre<EmbeddingCtx>,
+    outgoing_handler: Option<Arc<dyn crate::http::OutgoingHandler>>,
+    default_request_options: crate::http::RequestOptions,
 }
 
 impl RunnableComponent {
+    /// Handle the outgoing HTTP requests made by instances of this component,
+    /// in place of any handler set on the `Runtime`.
+    pub fn set_outgoing_handler(&mut self, handler: impl crate::http::OutgoingHandler + 'static) {
+        self.outgoing_handler = Some(Arc::new(handler));
+    }
+
+    /// Timeouts applied to the outgoing HTTP requests made by instances of
+    /// this component, where the guest does not set its own.
+    pub fn set_default_request_options(&mut self, options: crate::http::RequestOptions) {
+        self.default_request_options = options;
+    }
+
+    /// Configure a new instance of this component, then run it.
+    pub fn instance(&self) -> InstanceBuilder<'_> {
+        InstanceBuilder::new(self)
+    }
+
+    /// Run the component's `wasi:http/incoming-handler.handle` export on the
+    /// given request.
     pub fn create(
         &self,
         incoming: crate::http::IncomingRequest,
         headers: crate::http::Fields,
         body: crate::http::IncomingBody,
     ) -> Result<RunningComponent> {
-        let executor = Executor::new();
-        let clock = Clock::new();
-        let mut store = Store::new(
-            &self.engine,
-            EmbeddingCtx::new(executor.clone(), clock.clone()),
-        );
-        let mailbox = crate::http::ResponseOutparam::new();
-        let bindings_pre = self.bindings_pre.clone();
-        let fut = async move {
-            let instance = match bindings_pre.instantiate_async(&mut store).await {
-                Ok(i) => i,
-                Err(e) => return (store.into_data(), Err(e).context("instantiating")),
-            };
-            match instance
-                .wasi_http_incoming_handler_handle(
-                    &mut store,
-                    incoming,
-                    headers,
-                    body,
-                    mailbox.clone(),
-                )
-                .await
-            {
-                Ok(()) => {}
-                Err(e) => return (store.into_data(), Err(e).context("running handler")),
-            };
-
-            (store.into_data(), mailbox.into_inner())
-        };
-        let task = executor.spawn(fut);
-
-        Ok(RunningComponent {
-            clock,
-            executor,
-            output: Box::pin(task),
-        })
+        self.instance().create(incoming, headers, body)
+    }
+
+    /// Run the component's `wasi:cli/run.run` export, with the given
+    /// arguments and environment variables.
+    pub fn create_command(
+        &self,
+        arguments: Vec<String>,
+        environment: Vec<(String, String)>,
+    ) -> Result<RunningComponent> {
+        self.instance()
+            .args(arguments)
+            .envs(environment)
+            .create_command()
+    }
+}
+
+/// Whether a guest exited successfully.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Success,
+    Failure,
+}
+
+impl From<Result<(), ()>> for ExitStatus {
+    fn from(code: Result<(), ()>) -> Self {
+        match code {
+            Ok(()) => ExitStatus::Success,
+            Err(()) => ExitStatus::Failure,
+        }
+    }
+}
+
+// `wasi:cli/exit.exit` traps with an ExitStatus error, so that it unwinds the guest. The embedding
+// can downcast to it.
+impl core::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ExitStatus::Success => write!(f, "wasi exit success"),
+            ExitStatus::Failure => write!(f, "wasi exit error"),
+        }
+    }
+}
+impl core::error::Error for ExitStatus {}
+
+/// Exiting and running out of fuel trap, but are reported as completions rather than errors.
+fn exited(e: anyhow::Error) -> Result<Completion> {
+    if let Some(code) = e.downcast_ref::<ExitStatus>() {
+        return Ok(Completion::Exited { code: *code });
     }
+    if e.downcast_ref::<wasmtime::Trap>() == Some(&wasmtime::Trap::OutOfFuel) {
+        return Ok(Completion::OutOfFuel);
+    }
+    Err(e)
+}
+
+/// The outcome of a component run that did not fail.
+pub enum Completion {
+    /// The incoming-handler sent a response to its response-outparam.
+    Response(crate::http::Response),
+    /// The command's `run` returned, or the guest called `wasi:cli/exit.exit`
+    /// during either kind of run.
+    Exited { code: ExitStatus },
+    /// The guest used up the fuel given to `InstanceBuilder::fuel` before
+    /// completing.
+    OutOfFuel,
+}
+
+/// What a `RunningComponent` needs in order to make progress. See
+/// `RunningComponent::state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentState {
+    /// The component has completed, and `check_complete` gives its result.
+    Complete,
+    /// Tasks are ready to run: step the scheduler.
+    Runnable,
+    /// No task is ready, but one is waiting on a deadline: advance the clock
+    /// to it.
+    Sleeping { deadline: u64 },
+    /// No task is ready and no deadline is pending, but a task is waiting on
+    /// the host, such as for more stdin or for output to be drained, or a
+    /// `HostSource` is held.
+    AwaitingHost,
+    /// Nothing can ever make progress: no task is ready, no deadline is
+    /// pending, and nothing is waiting on the host. The component is
+    /// deadlocked, and `RunningComponent::blocked_tasks` says on what.
+    Quiescent,
 }
 
 pub struct RunningComponent {
-    clock: Clock,
-    executor: Executor,
-    output: Pin<
-        Box<
-            Task<(
-                EmbeddingCtx,
-                Result<(crate::http::OutgoingResponse, crate::http::ImmutFields)>,
-            )>,
-        >,
-    >,
+    scheduler: Scheduler,
+    request_body: Option<ChunkFeed>,
+    stdin: ChunkFeed,
+    captured: output::CapturedOutput,
+    fs_changes: fs::ChangeLog,
+    output: Pin<Box<Task<(EmbeddingCtx, Result<Completion>)>>>,
 }
 
 impl RunningComponent {
+    /// The scheduler this instance runs on. Unless one was given to
+    /// `InstanceBuilder::scheduler`, the instance has a scheduler of its own.
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
     pub fn earliest_deadline(&self) -> Option<u64> {
-        self.executor.earliest_deadline()
+        self.scheduler.earliest_deadline()
+    }
+
+    /// Whether the component has completed, and if not, what it needs in
+    /// order to make progress. States other than `Complete` describe the
+    /// whole scheduler, including any other instances sharing it.
+    pub fn state(&self) -> ComponentState {
+        if self.output.is_finished() {
+            ComponentState::Complete
+        } else if self.scheduler.executor().queued() > 0 {
+            ComponentState::Runnable
+        } else if let Some(deadline) = self.scheduler.earliest_deadline() {
+            ComponentState::Sleeping { deadline }
+        } else if self.scheduler.is_quiescent() {
+            ComponentState::Quiescent
+        } else {
+            ComponentState::AwaitingHost
+        }
+    }
+
+    /// Every unfinished task on the scheduler, with what it is waiting on.
+    /// See `Scheduler::blocked_tasks`.
+    pub fn blocked_tasks(&self) -> Vec<BlockedTask> {
+        self.scheduler.blocked_tasks()
     }
 
     pub fn increment_clock(&self) {
-        self.clock.set(self.clock.get() + 1);
-        self.check_for_wake();
+        self.scheduler.increment_clock()
     }
 
     pub fn advance_clock(&self, to: u64) {
-        self.clock.set(to);
-        self.check_for_wake();
+        self.scheduler.advance_clock(to)
+    }
+
+    /// Push another chunk of the incoming request body to the guest. Fails if
+    /// the body has already been finished.
+    pub fn push_body_chunk(&self, chunk: impl Into<Bytes>) -> Result<()> {
+        self.request_body
+            .as_ref()
+            .ok_or_else(|| anyhow!("component is not handling an http request"))?
+            .push(chunk.into())
     }
 
-    fn check_for_wake(&self) {
-        for waker in self.executor.ready_deadlines(self.clock.get()) {
-            waker.wake()
+    /// Signal that the incoming request body is complete. Once the guest has
+    /// read all pushed chunks, its input stream reports closed.
+    pub fn finish_body(&self) {
+        if let Some(request_body) = &self.request_body {
+            request_body.close()
         }
     }
 
+    /// Push more input to the guest's stdin, waking the guest if it is
+    /// waiting on it. Fails unless the instance was built with
+    /// `InstanceBuilder::stdin_streaming` and stdin is not yet closed.
+    pub fn push_stdin(&self, chunk: impl Into<Bytes>) -> Result<()> {
+        self.stdin.push(chunk.into()).context("pushing to stdin")
+    }
+
+    /// Close the guest's stdin. Once the guest has read all pushed input, its
+    /// stdin stream reports closed.
+    pub fn close_stdin(&self) {
+        self.stdin.close()
+    }
+
+    /// Everything the guest has written to stdout and stderr so far. The
+    /// report from `check_complete` is a rendering of this, with
+    /// `OutputLog::report`.
+    pub fn output(&self) -> output::OutputLog {
+        self.captured.log()
+    }
+
+    /// Take everything the guest has written to stdout and stderr so far,
+    /// leaving it out of later calls to `output` and the final report. This
+    /// makes room under any `OutputLimit`, and unblocks a guest waiting on
+    /// `OutputLimit::Backpressure`.
+    pub fn drain_output(&self) -> output::OutputLog {
+        self.captured.drain()
+    }
+
+    /// The changes the guest has made so far to its preopened directories,
+    /// oldest first. These are also listed in the report from
+    /// `check_complete`.
+    pub fn fs_changes(&self) -> Vec<fs::Change> {
+        self.fs_changes.changes()
+    }
+
+    /// Wait for the component to complete, while its scheduler is stepped
+    /// elsewhere. Gives the same report and result as `check_complete`.
+    pub async fn completion(self) -> (String, Result<Completion>) {
+        let (ctx, res) = self.output.await;
+        (ctx.report(), res)
+    }
+
+    /// Run the scheduler's ready tasks, including those of any other
+    /// instances sharing it.
     pub fn step(&mut self) -> usize {
-        self.executor.step()
+        self.scheduler.step()
+    }
+
+    /// Run at most `max` of the scheduler's tasks. See `Scheduler::step_n`.
+    pub fn step_n(&mut self, max: usize) -> StepOutcome {
+        self.scheduler.step_n(max)
+    }
+
+    /// Run the scheduler's tasks until `stop` returns true or none are
+    /// ready. See `Scheduler::step_until`.
+    pub fn step_until(&mut self, stop: impl FnMut() -> bool) -> StepOutcome {
+        self.scheduler.step_until(stop)
     }
 
-    pub fn check_complete(
-        &mut self,
-    ) -> Option<(
-        String,
-        Result<(crate::http::OutgoingResponse, crate::http::ImmutFields)>,
-    )> {
+    pub fn check_complete(&mut self) -> Option<(String, Result<Completion>)> {
         match self
             .output
             .as_mut()
@@ -0,0 +1,432 @@
+use crate::clock::Clock;
+use crate::runtime::Executor;
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use anyhow::anyhow;
+use bytes::Bytes;
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::ops::RangeBounds;
+use core::task::{Poll, Waker};
+
+use wasmtime_wasi_io::poll::Pollable;
+use wasmtime_wasi_io::streams::{OutputStream, StreamError, StreamResult};
+
+/// Which of the guest's output streams a write went to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stdio {
+    Stdout,
+    Stderr,
+}
+
+impl Stdio {
+    fn index(self) -> usize {
+        match self {
+            Stdio::Stdout => 0,
+            Stdio::Stderr => 1,
+        }
+    }
+}
+
+impl core::fmt::Display for Stdio {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Stdio::Stdout => write!(f, "stdout"),
+            Stdio::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// One write by the guest, at a time on the virtual Clock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub time: u64,
+    pub stream: Stdio,
+    pub bytes: Bytes,
+}
+
+/// Everything the guest wrote to stdout and stderr, in the order it was
+/// written.
+#[derive(Debug, Clone, Default)]
+pub struct OutputLog {
+    records: Vec<Record>,
+    discarded: [u64; 2],
+}
+
+impl OutputLog {
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+    pub fn stream(&self, stream: Stdio) -> impl Iterator<Item = &Record> {
+        self.records.iter().filter(move |r| r.stream == stream)
+    }
+    /// Everything written to one stream, concatenated.
+    pub fn contents(&self, stream: Stdio) -> Vec<u8> {
+        self.stream(stream)
+            .flat_map(|r| r.bytes.iter().copied())
+            .collect()
+    }
+    pub fn stdout(&self) -> Vec<u8> {
+        self.contents(Stdio::Stdout)
+    }
+    pub fn stderr(&self) -> Vec<u8> {
+        self.contents(Stdio::Stderr)
+    }
+    /// The contents of one stream split into lines, regardless of how the
+    /// guest split its writes. Invalid UTF-8 is replaced, and a final
+    /// unterminated line is included.
+    pub fn lines(&self, stream: Stdio) -> Vec<String> {
+        let contents = self.contents(stream);
+        let mut lines = contents
+            .split(|b| *b == b'\n')
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect::<Vec<_>>();
+        if contents.is_empty() || contents.ends_with(b"\n") {
+            lines.pop();
+        }
+        lines
+    }
+    /// How many bytes written to a stream were discarded by its
+    /// `OutputLimit`.
+    pub fn discarded(&self, stream: Stdio) -> u64 {
+        self.discarded[stream.index()]
+    }
+    /// Only the records written at times within `range`.
+    pub fn between(&self, range: impl RangeBounds<u64>) -> OutputLog {
+        OutputLog {
+            discarded: self.discarded,
+            records: self
+                .records
+                .iter()
+                .filter(|r| range.contains(&r.time))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Render each stream's writes, one per line, with the time it was
+    /// written.
+    pub fn report(&self, out: &mut impl core::fmt::Write) -> core::fmt::Result {
+        for stream in [Stdio::Stdout, Stdio::Stderr] {
+            writeln!(out, "{stream}:")?;
+            for record in self.stream(stream) {
+                let contents = String::from_utf8_lossy(&record.bytes);
+                writeln!(out, "{:08} {:?}", record.time, contents)?;
+            }
+            if self.discarded(stream) > 0 {
+                writeln!(out, "({} bytes discarded)", self.discarded(stream))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Receives each write to the guest's stdout and stderr as it happens, while
+/// the guest is still running. See `InstanceBuilder::output_sink`.
+pub trait OutputSink: Send + Sync {
+    fn write(&self, record: &Record);
+}
+
+impl<F: Fn(&Record) + Send + Sync> OutputSink for F {
+    fn write(&self, record: &Record) {
+        self(record)
+    }
+}
+
+/// How much of one stream's output is kept, and what happens beyond that.
+/// Limits count the bytes held in the capture, so output removed with
+/// `RunningComponent::drain_output` no longer counts against them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputLimit {
+    #[default]
+    Unbounded,
+    /// Keep writes until the limit is reached, and discard everything
+    /// after.
+    Truncate(usize),
+    /// Keep the most recent writes, discarding the oldest to stay within the
+    /// limit.
+    DropOldest(usize),
+    /// Hold at most the limit. Once it is reached, the stream's
+    /// `check-write` returns 0 and its pollable is not ready until the
+    /// embedder drains output.
+    Backpressure(usize),
+}
+
+/// The log shared by an instance's stdout and stderr, so that the order of
+/// writes across both is kept. Writes are passed on to the sink, if any, as
+/// well as kept in the log.
+#[derive(Clone)]
+pub(crate) struct CapturedOutput {
+    inner: Rc<RefCell<CapturedInner>>,
+    sink: Option<Arc<dyn OutputSink>>,
+}
+
+struct CapturedInner {
+    records: VecDeque<Record>,
+    limits: [OutputLimit; 2],
+    buffered: [usize; 2],
+    discarded: [u64; 2],
+    wakers: Vec<Waker>,
+}
+
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for CapturedOutput {}
+unsafe impl Sync for CapturedOutput {}
+
+impl CapturedOutput {
+    pub fn new(
+        sink: Option<Arc<dyn OutputSink>>,
+        stdout_limit: OutputLimit,
+        stderr_limit: OutputLimit,
+    ) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(CapturedInner {
+                records: VecDeque::new(),
+                limits: [stdout_limit, stderr_limit],
+                buffered: [0; 2],
+                discarded: [0; 2],
+                wakers: Vec::new(),
+            })),
+            sink,
+        }
+    }
+    /// The sink sees every write in full, even those the limits discard.
+    pub fn record(&self, mut record: Record) {
+        if let Some(sink) = &self.sink {
+            sink.write(&record);
+        }
+        let mut inner = self.inner.borrow_mut();
+        let i = record.stream.index();
+        match inner.limits[i] {
+            OutputLimit::Truncate(limit) => {
+                let room = limit.saturating_sub(inner.buffered[i]);
+                if record.bytes.len() > room {
+                    inner.discarded[i] += (record.bytes.len() - room) as u64;
+                    record.bytes.truncate(room);
+                }
+            }
+            OutputLimit::DropOldest(limit) => {
+                if record.bytes.len() > limit {
+                    let excess = record.bytes.len() - limit;
+                    inner.discarded[i] += excess as u64;
+                    record.bytes = record.bytes.split_off(excess);
+                }
+                while inner.buffered[i] + record.bytes.len() > limit {
+                    let oldest = inner
+                        .records
+                        .iter()
+                        .position(|r| r.stream == record.stream)
+                        .expect("buffered bytes are in records");
+                    let dropped = inner.records.remove(oldest).expect("position is in bounds");
+                    inner.buffered[i] -= dropped.bytes.len();
+                    inner.discarded[i] += dropped.bytes.len() as u64;
+                }
+            }
+            OutputLimit::Unbounded | OutputLimit::Backpressure(_) => {}
+        }
+        if !record.bytes.is_empty() {
+            inner.buffered[i] += record.bytes.len();
+            inner.records.push_back(record);
+        }
+    }
+    /// How many more bytes may be written to a stream right now.
+    fn capacity(&self, stream: Stdio) -> usize {
+        let inner = self.inner.borrow();
+        let i = stream.index();
+        match inner.limits[i] {
+            OutputLimit::Backpressure(limit) => limit.saturating_sub(inner.buffered[i]),
+            _ => usize::MAX,
+        }
+    }
+    pub fn log(&self) -> OutputLog {
+        let inner = self.inner.borrow();
+        OutputLog {
+            records: inner.records.iter().cloned().collect(),
+            discarded: inner.discarded,
+        }
+    }
+    /// Take everything captured so far out of the log, making room for more
+    /// output and waking any guest blocked on backpressure.
+    pub fn drain(&self) -> OutputLog {
+        let mut inner = self.inner.borrow_mut();
+        let log = OutputLog {
+            records: inner.records.drain(..).collect(),
+            discarded: inner.discarded,
+        };
+        inner.buffered = [0; 2];
+        for waker in inner.wakers.drain(..) {
+            waker.wake()
+        }
+        log
+    }
+}
+
+/// The output-stream given to the guest for stdout or stderr.
+#[derive(Clone)]
+pub(crate) struct StdioWriter {
+    stream: Stdio,
+    clock: Clock,
+    executor: Executor,
+    captured: CapturedOutput,
+}
+
+impl StdioWriter {
+    pub fn new(stream: Stdio, clock: Clock, executor: Executor, captured: CapturedOutput) -> Self {
+        Self {
+            stream,
+            clock,
+            executor,
+            captured,
+        }
+    }
+}
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for StdioWriter {}
+unsafe impl Sync for StdioWriter {}
+
+#[wasmtime_wasi_io::async_trait]
+impl Pollable for StdioWriter {
+    async fn ready(&mut self) {
+        poll_fn(|cx| {
+            if self.captured.capacity(self.stream) > 0 {
+                return Poll::Ready(());
+            }
+            let mut inner = self.captured.inner.borrow_mut();
+            if !inner.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                inner.wakers.push(cx.waker().clone());
+            }
+            self.executor
+                .blocked_on_host(format!("{} until output is drained", self.stream));
+            Poll::Pending
+        })
+        .await
+    }
+}
+impl OutputStream for StdioWriter {
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(self.captured.capacity(self.stream))
+    }
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        if bytes.len() > self.captured.capacity(self.stream) {
+            return Err(StreamError::Trap(anyhow!(
+                "write of {} bytes to {} exceeds check-write",
+                bytes.len(),
+                self.stream
+            )));
+        }
+        self.captured.record(Record {
+            time: self.clock.get(),
+            stream: self.stream,
+            bytes,
+        });
+        Ok(())
+    }
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::task::Wake;
+    use core::future::Future;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::task::Context;
+
+    fn captured(stdout_limit: OutputLimit, stderr_limit: OutputLimit) -> CapturedOutput {
+        CapturedOutput::new(None, stdout_limit, stderr_limit)
+    }
+
+    fn record(captured: &CapturedOutput, stream: Stdio, bytes: &'static [u8]) {
+        captured.record(Record {
+            time: 0,
+            stream,
+            bytes: Bytes::from_static(bytes),
+        });
+    }
+
+    #[derive(Default)]
+    struct CountWakes(AtomicUsize);
+    impl Wake for CountWakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn truncate_counts_discarded_bytes() {
+        let captured = captured(OutputLimit::Truncate(5), OutputLimit::Unbounded);
+        record(&captured, Stdio::Stdout, b"hello world");
+        record(&captured, Stdio::Stdout, b"more");
+        record(&captured, Stdio::Stderr, b"kept");
+        let log = captured.log();
+        assert_eq!(log.stdout(), b"hello");
+        assert_eq!(log.discarded(Stdio::Stdout), 10);
+        assert_eq!(log.stderr(), b"kept");
+        assert_eq!(log.discarded(Stdio::Stderr), 0);
+    }
+
+    #[test]
+    fn drop_oldest_evicts_whole_writes_per_stream() {
+        let captured = captured(OutputLimit::DropOldest(6), OutputLimit::DropOldest(4));
+        record(&captured, Stdio::Stdout, b"abc");
+        record(&captured, Stdio::Stderr, b"12");
+        record(&captured, Stdio::Stdout, b"def");
+        record(&captured, Stdio::Stderr, b"34");
+        record(&captured, Stdio::Stdout, b"gh");
+        record(&captured, Stdio::Stderr, b"5");
+        let log = captured.log();
+        assert_eq!(log.stdout(), b"defgh");
+        assert_eq!(log.discarded(Stdio::Stdout), 3);
+        assert_eq!(log.stderr(), b"345");
+        assert_eq!(log.discarded(Stdio::Stderr), 2);
+        let order = log
+            .records()
+            .iter()
+            .map(|r| (r.stream, r.bytes.as_ref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            [
+                (Stdio::Stdout, &b"def"[..]),
+                (Stdio::Stderr, &b"34"[..]),
+                (Stdio::Stdout, &b"gh"[..]),
+                (Stdio::Stderr, &b"5"[..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn backpressure_blocks_until_drained() {
+        let captured = captured(OutputLimit::Backpressure(4), OutputLimit::Unbounded);
+        let mut writer = StdioWriter::new(
+            Stdio::Stdout,
+            Clock::new(),
+            Executor::new(),
+            captured.clone(),
+        );
+        assert_eq!(writer.check_write().unwrap(), 4);
+        writer.write(Bytes::from_static(b"abcd")).unwrap();
+        assert_eq!(writer.check_write().unwrap(), 0);
+        assert!(writer.write(Bytes::from_static(b"e")).is_err());
+
+        let wakes = Arc::new(CountWakes::default());
+        let waker = Waker::from(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut ready = writer.ready();
+        assert!(ready.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+
+        assert_eq!(captured.drain().stdout(), b"abcd");
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        assert!(ready.as_mut().poll(&mut cx).is_ready());
+        drop(ready);
+        assert_eq!(writer.check_write().unwrap(), 4);
+    }
+}
@@ -0,0 +1,80 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Entropy supplied by the embedder, which fills the buffer it is given.
+pub type EntropyFn = Arc<dyn Fn(&mut [u8]) + Send + Sync>;
+
+/// Where an instance gets its random bytes from. Unless the embedder supplies
+/// entropy, this is a generator seeded per instance, so that runs are
+/// reproducible alongside the virtual clock.
+pub enum RandomSource {
+    Seeded(Xoshiro256),
+    Entropy(EntropyFn),
+}
+
+impl RandomSource {
+    pub fn new(seed: u64, entropy: Option<EntropyFn>) -> Self {
+        match entropy {
+            Some(entropy) => RandomSource::Entropy(entropy),
+            None => RandomSource::Seeded(Xoshiro256::new(seed)),
+        }
+    }
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        match self {
+            RandomSource::Seeded(rng) => rng.fill_bytes(buf),
+            RandomSource::Entropy(entropy) => entropy(buf),
+        }
+    }
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        self.fill_bytes(&mut buf);
+        buf
+    }
+    pub fn u64(&mut self) -> u64 {
+        let mut buf = [0u8; 8];
+        self.fill_bytes(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+}
+
+/// xoshiro256**, seeded through splitmix64 as its authors recommend. It is
+/// fast and has good statistical properties, but is not cryptographically
+/// secure.
+pub struct Xoshiro256 {
+    s: [u64; 4],
+}
+
+impl Xoshiro256 {
+    pub fn new(seed: u64) -> Self {
+        let mut state = seed;
+        let mut splitmix64 = || {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        };
+        Xoshiro256 {
+            s: [splitmix64(), splitmix64(), splitmix64(), splitmix64()],
+        }
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.s;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
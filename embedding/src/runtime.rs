@@ -1,10 +1,15 @@
-use alloc::collections::VecDeque;
+use crate::scheduler::BlockedTask;
+use alloc::borrow::Cow;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
 use alloc::rc::Rc;
+use alloc::string::String;
 use alloc::vec::Vec;
 use async_task::{Runnable, Task};
 use core::cell::RefCell;
-use core::future::Future;
-use core::task::Waker;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::{Poll, Waker};
 
 #[derive(Clone, Debug)]
 pub struct Executor(Rc<RefCell<ExecutorInner>>);
@@ -15,24 +20,61 @@ unsafe impl Sync for Executor {}
 impl Executor {
     pub fn new() -> Self {
         Executor(Rc::new(RefCell::new(ExecutorInner {
-            deadlines: Vec::new(),
+            deadlines: BTreeMap::new(),
+            next_deadline_id: 0,
             runnables: VecDeque::new(),
+            tasks: BTreeMap::new(),
+            next_task_id: 0,
+            running: None,
+            host_sources: BTreeMap::new(),
+            next_host_source_id: 0,
         })))
     }
+    /// Run the tasks that were ready when the step began. Tasks that become
+    /// ready during the step, such as a guest yielding to refuel, run on the
+    /// next step, so that each step does a bounded amount of work.
     pub(crate) fn step(&self) -> usize {
+        let ready = self.queued();
+        self.run_until(ready, || false)
+    }
+    /// Run queued tasks, including any that become ready along the way, until
+    /// `max` have run, `stop` returns true, or the queue is empty. `stop` is
+    /// checked before each task. Returns how many ran.
+    pub(crate) fn run_until(&self, max: usize, mut stop: impl FnMut() -> bool) -> usize {
         let mut count = 0;
-        while let Some(runnable) = self.pop_runnable() {
+        while count < max && !stop() {
+            let Some(runnable) = self.pop_runnable() else {
+                break;
+            };
             runnable.run();
             count += 1;
         }
         count
     }
+    pub(crate) fn queued(&self) -> usize {
+        self.0.borrow().runnables.len()
+    }
 
-    pub fn spawn<F, R>(&self, future: F) -> Task<R>
+    /// Spawn a task. Its `name` identifies it in `blocked_tasks`.
+    pub fn spawn<F, R>(&self, name: impl Into<String>, future: F) -> Task<R>
     where
         F: Future<Output = R> + Send + 'static,
         R: Send + 'static,
     {
+        let tracked = TrackedTask {
+            executor: self.clone(),
+            id: self.0.borrow_mut().add_task(name.into()),
+        };
+        let future = async move {
+            let mut future = pin!(future);
+            poll_fn(|cx| {
+                tracked.enter();
+                let poll = future.as_mut().poll(cx);
+                tracked.leave();
+                poll
+            })
+            .await
+        };
         let this = self.clone();
         let schedule = move |runnable| this.push_runnable(runnable);
         let (runnable, task) = async_task::spawn(future, schedule);
@@ -46,8 +88,21 @@ impl Executor {
     fn pop_runnable(&self) -> Option<Runnable> {
         self.0.borrow_mut().runnables.pop_front()
     }
-    pub fn push_deadline(&self, deadline: u64, waker: Waker) {
-        self.0.borrow_mut().deadlines.push((deadline, waker))
+    /// Wake `waker` once the clock reaches `deadline`. The registration
+    /// lasts until it fires or is cancelled.
+    pub fn register_deadline(&self, deadline: u64, waker: Waker) -> DeadlineKey {
+        self.0.borrow_mut().register_deadline(deadline, waker)
+    }
+    /// Point a registration at the waker from the latest poll. A registration
+    /// that already fired is made again.
+    pub fn update_deadline(&self, key: DeadlineKey, waker: &Waker) {
+        self.0.borrow_mut().update_deadline(key, waker)
+    }
+    pub fn cancel_deadline(&self, key: DeadlineKey) {
+        self.0
+            .borrow_mut()
+            .deadlines
+            .remove(&(key.deadline, key.id));
     }
     pub fn earliest_deadline(&self) -> Option<u64> {
         self.0.borrow().earliest_deadline()
@@ -55,35 +110,192 @@ impl Executor {
     pub fn ready_deadlines(&self, now: u64) -> Vec<Waker> {
         self.0.borrow_mut().ready_deadlines(now)
     }
+
+    /// Note that the running task is waiting on `pollable`, until it is next
+    /// polled. Does nothing outside of a task.
+    pub(crate) fn blocked_on(&self, pollable: impl Into<Cow<'static, str>>) {
+        self.0.borrow_mut().blocked_on(pollable.into(), false)
+    }
+    /// As `blocked_on`, for a pollable that only the host can make ready,
+    /// such as stdin the host has not yet closed.
+    pub(crate) fn blocked_on_host(&self, pollable: impl Into<Cow<'static, str>>) {
+        self.0.borrow_mut().blocked_on(pollable.into(), true)
+    }
+    /// Await `future`, noting the running task as blocked on `pollable`
+    /// whenever it is pending.
+    pub(crate) async fn wait_on<F: Future>(
+        &self,
+        pollable: impl Into<Cow<'static, str>>,
+        future: F,
+    ) -> F::Output {
+        let pollable = pollable.into();
+        let mut future = pin!(future);
+        poll_fn(|cx| {
+            let poll = future.as_mut().poll(cx);
+            if poll.is_pending() {
+                self.blocked_on(pollable.clone());
+            }
+            poll
+        })
+        .await
+    }
+    /// Every live task, with what it was waiting on when it last yielded,
+    /// including the deadlines it registered.
+    pub(crate) fn blocked_tasks(&self) -> Vec<BlockedTask> {
+        let inner = self.0.borrow();
+        let mut blocked = inner
+            .tasks
+            .iter()
+            .map(|(id, task)| BlockedTask {
+                id: *id,
+                name: task.name.clone(),
+                blocked_on: task
+                    .blocked_on
+                    .iter()
+                    .map(|wait| String::from(&*wait.pollable))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        for ((deadline, _), (_, task)) in &inner.deadlines {
+            if let Some(task) = blocked.iter_mut().find(|t| Some(t.id) == *task) {
+                task.blocked_on.push(format!("deadline at {deadline}"));
+            }
+        }
+        blocked
+    }
+
+    pub(crate) fn add_host_source(&self, name: String) -> u64 {
+        let mut inner = self.0.borrow_mut();
+        let id = inner.next_host_source_id;
+        inner.next_host_source_id += 1;
+        inner.host_sources.insert(id, name);
+        id
+    }
+    pub(crate) fn remove_host_source(&self, id: u64) {
+        self.0.borrow_mut().host_sources.remove(&id);
+    }
+
+    /// Whether nothing can ever happen again: no task is ready, no deadline
+    /// is pending, no task waits on the host and no host source is
+    /// registered.
+    pub(crate) fn is_quiescent(&self) -> bool {
+        let inner = self.0.borrow();
+        inner.runnables.is_empty()
+            && inner.deadlines.is_empty()
+            && inner.host_sources.is_empty()
+            && !inner
+                .tasks
+                .values()
+                .any(|task| task.blocked_on.iter().any(|wait| wait.host))
+    }
+}
+
+/// Keeps a spawned task's entry in the executor while its future is alive.
+struct TrackedTask {
+    executor: Executor,
+    id: u64,
+}
+impl TrackedTask {
+    // What the task waits on is noted afresh each time it is polled.
+    fn enter(&self) {
+        let mut inner = self.executor.0.borrow_mut();
+        if let Some(task) = inner.tasks.get_mut(&self.id) {
+            task.blocked_on.clear();
+        }
+        inner.running = Some(self.id);
+    }
+    fn leave(&self) {
+        self.executor.0.borrow_mut().running = None;
+    }
+}
+impl Drop for TrackedTask {
+    fn drop(&mut self) {
+        self.executor.0.borrow_mut().tasks.remove(&self.id);
+    }
+}
+
+/// Identifies one registration made with `Executor::register_deadline`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineKey {
+    deadline: u64,
+    id: u64,
 }
 
 #[derive(Debug)]
 struct ExecutorInner {
-    deadlines: Vec<(u64, Waker)>,
+    // Registered wakers, ordered by deadline so the earliest is first and all
+    // that are due can be split off at once. The id keeps registrations for
+    // the same deadline apart. Each also records the task that registered
+    // it, if any.
+    deadlines: BTreeMap<(u64, u64), (Waker, Option<u64>)>,
+    next_deadline_id: u64,
     runnables: VecDeque<Runnable>,
+    tasks: BTreeMap<u64, TaskInfo>,
+    next_task_id: u64,
+    // The task being polled, if any.
+    running: Option<u64>,
+    host_sources: BTreeMap<u64, String>,
+    next_host_source_id: u64,
+}
+
+#[derive(Debug)]
+struct TaskInfo {
+    name: String,
+    blocked_on: Vec<Wait>,
+}
+
+#[derive(Debug)]
+struct Wait {
+    pollable: Cow<'static, str>,
+    host: bool,
 }
 
 impl ExecutorInner {
-    fn earliest_deadline(&self) -> Option<u64> {
-        self.deadlines.iter().map(|(d, _)| d).min().copied()
+    fn register_deadline(&mut self, deadline: u64, waker: Waker) -> DeadlineKey {
+        let id = self.next_deadline_id;
+        self.next_deadline_id += 1;
+        self.deadlines.insert((deadline, id), (waker, self.running));
+        DeadlineKey { deadline, id }
     }
-    fn ready_deadlines(&mut self, now: u64) -> Vec<Waker> {
-        let mut i = 0;
-        let mut wakers = Vec::new();
-        // This is basically https://doc.rust-lang.org/std/vec/struct.Vec.html#method.extract_if,
-        // which is unstable
-        while i < self.deadlines.len() {
-            if let Some((deadline, _)) = self.deadlines.get(i) {
-                if *deadline <= now {
-                    let (_, waker) = self.deadlines.remove(i);
-                    wakers.push(waker);
-                } else {
-                    i += 1;
+    fn update_deadline(&mut self, key: DeadlineKey, waker: &Waker) {
+        let running = self.running;
+        match self.deadlines.get_mut(&(key.deadline, key.id)) {
+            Some((registered, task)) => {
+                if !registered.will_wake(waker) {
+                    *registered = waker.clone();
                 }
-            } else {
-                break;
+                *task = running;
+            }
+            None => {
+                self.deadlines
+                    .insert((key.deadline, key.id), (waker.clone(), running));
             }
         }
-        wakers
+    }
+    fn add_task(&mut self, name: String) -> u64 {
+        let id = self.next_task_id;
+        self.next_task_id += 1;
+        let blocked_on = Vec::new();
+        self.tasks.insert(id, TaskInfo { name, blocked_on });
+        id
+    }
+    fn blocked_on(&mut self, pollable: Cow<'static, str>, host: bool) {
+        let Some(task) = self.running.and_then(|id| self.tasks.get_mut(&id)) else {
+            return;
+        };
+        if !task.blocked_on.iter().any(|w| w.pollable == pollable) {
+            task.blocked_on.push(Wait { pollable, host });
+        }
+    }
+    fn earliest_deadline(&self) -> Option<u64> {
+        self.deadlines.first_key_value().map(|((d, _), _)| *d)
+    }
+    fn ready_deadlines(&mut self, now: u64) -> Vec<Waker> {
+        let later = match now.checked_add(1) {
+            Some(after_now) => self.deadlines.split_off(&(after_now, 0)),
+            None => BTreeMap::new(),
+        };
+        let ready = core::mem::replace(&mut self.deadlines, later);
+        ready.into_values().map(|(waker, _)| waker).collect()
     }
 }
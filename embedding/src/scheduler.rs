@@ -0,0 +1,183 @@
+use crate::clock::{Clock, Timer};
+use crate::runtime::Executor;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// An Executor and virtual Clock that any number of instances can share, so
+/// that they run in one loop and see the same time. Pass one to
+/// `InstanceBuilder::scheduler` to create an instance on it.
+///
+/// Stepping runs every instance on the scheduler that is ready, and
+/// advancing the clock wakes every instance whose deadline has passed.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    executor: Executor,
+    clock: Clock,
+}
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for Scheduler {}
+unsafe impl Sync for Scheduler {}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler {
+            executor: Executor::new(),
+            clock: Clock::new(),
+        }
+    }
+
+    pub(crate) fn executor(&self) -> &Executor {
+        &self.executor
+    }
+    pub(crate) fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    pub fn now(&self) -> u64 {
+        self.clock.get()
+    }
+    pub fn timer(&self) -> Timer {
+        Timer::new(self.executor.clone(), self.clock.clone())
+    }
+
+    pub fn earliest_deadline(&self) -> Option<u64> {
+        self.executor.earliest_deadline()
+    }
+
+    pub fn increment_clock(&self) {
+        self.clock.set(self.clock.get() + 1);
+        self.check_for_wake();
+    }
+
+    pub fn advance_clock(&self, to: u64) {
+        self.clock.set(to);
+        self.check_for_wake();
+    }
+
+    fn check_for_wake(&self) {
+        for waker in self.executor.ready_deadlines(self.clock.get()) {
+            waker.wake()
+        }
+    }
+
+    /// Run the tasks that were ready when the step began. Tasks they wake run
+    /// on the next step. Returns how many ran.
+    pub fn step(&self) -> usize {
+        self.executor.step()
+    }
+
+    /// Run at most `max` tasks, including any woken along the way.
+    pub fn step_n(&self, max: usize) -> StepOutcome {
+        let ran = self.executor.run_until(max, || false);
+        self.outcome(ran)
+    }
+
+    /// Run tasks, including any woken along the way, until `stop` returns
+    /// true or none are ready. `stop` is checked before each task, so it can
+    /// enforce a budget such as a host time limit.
+    pub fn step_until(&self, stop: impl FnMut() -> bool) -> StepOutcome {
+        let ran = self.executor.run_until(usize::MAX, stop);
+        self.outcome(ran)
+    }
+
+    /// Whether nothing on the scheduler can ever make progress: no task is
+    /// ready, no deadline is pending, no task is waiting on the host, and no
+    /// `HostSource` is held. Tasks that have not finished by then are
+    /// deadlocked; `blocked_tasks` says what they are waiting on.
+    pub fn is_quiescent(&self) -> bool {
+        self.executor.is_quiescent()
+    }
+
+    /// Every unfinished task on the scheduler, with the pollables it was
+    /// waiting on when it last yielded.
+    pub fn blocked_tasks(&self) -> Vec<BlockedTask> {
+        self.executor.blocked_tasks()
+    }
+
+    /// Declare an event source outside the scheduler, such as a channel an
+    /// `OutgoingHandler` reads from, that may wake a task. The scheduler is
+    /// not quiescent while the returned `HostSource` is held.
+    pub fn host_source(&self, name: impl Into<String>) -> HostSource {
+        HostSource {
+            executor: self.executor.clone(),
+            id: self.executor.add_host_source(name.into()),
+        }
+    }
+
+    fn outcome(&self, ran: usize) -> StepOutcome {
+        StepOutcome {
+            ran,
+            queued: self.executor.queued(),
+            earliest_deadline: self.executor.earliest_deadline(),
+        }
+    }
+}
+
+/// What a bounded step did, and what is left for the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepOutcome {
+    /// How many tasks ran.
+    pub ran: usize,
+    /// How many tasks are ready to run on the next step.
+    pub queued: usize,
+    /// The earliest deadline any task is waiting on.
+    pub earliest_deadline: Option<u64>,
+}
+
+impl StepOutcome {
+    /// Whether no tasks are ready, so only advancing the clock, or an event
+    /// from the host, can make progress.
+    pub fn is_idle(&self) -> bool {
+        self.queued == 0
+    }
+}
+
+/// A task on a scheduler that has not finished, and what it is waiting on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockedTask {
+    pub id: u64,
+    /// What spawned the task, such as "command" for an instance running
+    /// `wasi:cli/run`.
+    pub name: String,
+    /// The pollables the task was waiting on when it last yielded. Empty if
+    /// it is waiting on something the embedding does not know about.
+    pub blocked_on: Vec<String>,
+}
+
+impl core::fmt::Display for BlockedTask {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "task {} ({}) blocked on: ", self.id, self.name)?;
+        if self.blocked_on.is_empty() {
+            return write!(f, "unknown");
+        }
+        for (i, pollable) in self.blocked_on.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{pollable}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An event source registered with `Scheduler::host_source`. Dropping it
+/// unregisters it.
+pub struct HostSource {
+    executor: Executor,
+    id: u64,
+}
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for HostSource {}
+unsafe impl Sync for HostSource {}
+
+impl Drop for HostSource {
+    fn drop(&mut self) {
+        self.executor.remove_host_source(self.id);
+    }
+}
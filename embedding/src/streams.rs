@@ -1,25 +1,117 @@
 use crate::clock::Clock;
+use crate::runtime::Executor;
 
 use alloc::boxed::Box;
 use alloc::collections::VecDeque;
 use alloc::rc::Rc;
-use alloc::string::String;
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
 use bytes::Bytes;
 use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
 
 use wasmtime_wasi_io::poll::Pollable;
 use wasmtime_wasi_io::streams::{InputStream, OutputStream};
 
-pub struct NeverReadable;
+/// A sequence of byte chunks provided by the host, either all at once or
+/// pushed over time. Readers see the chunks in order, and their Pollable
+/// becomes ready whenever a chunk is pushed or the feed is closed.
+#[derive(Clone)]
+pub struct ChunkFeed(Rc<RefCell<FeedInner>>);
+
+struct FeedInner {
+    chunks: VecDeque<Bytes>,
+    closed: bool,
+    wakers: Vec<Waker>,
+    // Where to note tasks waiting on the feed, and what to call it.
+    watcher: Option<(Executor, &'static str)>,
+}
+
+impl ChunkFeed {
+    /// An open feed with no contents yet.
+    pub fn new() -> Self {
+        ChunkFeed(Rc::new(RefCell::new(FeedInner {
+            chunks: VecDeque::new(),
+            closed: false,
+            wakers: Vec::new(),
+            watcher: None,
+        })))
+    }
+    /// A closed feed with the entire contents given up front.
+    pub fn full(contents: Bytes) -> Self {
+        let feed = Self::new();
+        if !contents.is_empty() {
+            feed.0.borrow_mut().chunks.push_back(contents);
+        }
+        feed.close();
+        feed
+    }
+    pub fn push(&self, chunk: Bytes) -> Result<()> {
+        let mut inner = self.0.borrow_mut();
+        if inner.closed {
+            bail!("cannot push to a closed feed");
+        }
+        if !chunk.is_empty() {
+            inner.chunks.push_back(chunk);
+            inner.wake();
+        }
+        Ok(())
+    }
+    pub fn close(&self) {
+        let mut inner = self.0.borrow_mut();
+        inner.closed = true;
+        inner.wake();
+    }
+    /// Note tasks waiting on the feed with `executor`, as waiting on the host
+    /// for `name`.
+    pub fn watch(&self, executor: &Executor, name: &'static str) {
+        self.0.borrow_mut().watcher = Some((executor.clone(), name));
+    }
+}
+
+impl FeedInner {
+    fn wake(&mut self) {
+        // Waking schedules the waiting task back onto the executor.
+        for waker in self.wakers.drain(..) {
+            waker.wake()
+        }
+    }
+}
+
+// SAFETY: only will consume this crate in single-threaded environment
+unsafe impl Send for ChunkFeed {}
+unsafe impl Sync for ChunkFeed {}
+
 #[wasmtime_wasi_io::async_trait]
-impl Pollable for NeverReadable {
+impl Pollable for ChunkFeed {
     async fn ready(&mut self) {
-        futures_lite::future::pending().await
+        poll_fn(|cx| {
+            let mut inner = self.0.borrow_mut();
+            if inner.closed || !inner.chunks.is_empty() {
+                Poll::Ready(())
+            } else {
+                if !inner.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    inner.wakers.push(cx.waker().clone());
+                }
+                if let Some((executor, name)) = &inner.watcher {
+                    executor.blocked_on_host(*name);
+                }
+                Poll::Pending
+            }
+        })
+        .await
     }
 }
-impl InputStream for NeverReadable {
-    fn read(&mut self, _: usize) -> wasmtime_wasi_io::streams::StreamResult<Bytes> {
-        unreachable!("never ready for reading")
+impl InputStream for ChunkFeed {
+    fn read(&mut self, size: usize) -> wasmtime_wasi_io::streams::StreamResult<Bytes> {
+        let mut inner = self.0.borrow_mut();
+        match inner.chunks.front_mut() {
+            Some(chunk) if chunk.len() > size => Ok(chunk.split_to(size)),
+            Some(_) => Ok(inner.chunks.pop_front().unwrap()),
+            None if inner.closed => Err(wasmtime_wasi_io::streams::StreamError::Closed),
+            None => Ok(Bytes::new()),
+        }
     }
 }
 
@@ -35,11 +127,8 @@ impl TimestampedWrites {
             log: Rc::new(RefCell::new(VecDeque::new())),
         }
     }
-    pub fn report(&self, out: &mut impl core::fmt::Write) -> core::fmt::Result {
-        for (time, line) in self.log.borrow_mut().iter() {
-            write!(out, "{:08} {:?}\n", time, String::from_utf8_lossy(line))?;
-        }
-        Ok(())
+    pub fn chunks(&self) -> Vec<(u64, Bytes)> {
+        self.log.borrow().iter().cloned().collect()
     }
 }
 // SAFETY: only will consume this crate in single-threaded environment
@@ -1,50 +1,74 @@
 use anyhow::{anyhow, Result};
 use wasmtime::component::Component;
-use wasmtime::{Config, Engine};
+use wasmtime::Engine;
 
 fn main() -> Result<()> {
-    let mut args = std::env::args();
-    let _current_exe = args.next();
+    let mut args = std::env::args().skip(1).peekable();
+    let command = args.next_if(|arg| arg == "--command").is_some();
     let wasm_path = args
         .next()
         .ok_or_else(|| anyhow!("missing required argument: wasm path"))?;
 
-    let mut config = Config::new();
-    config.async_support(true);
-    let engine = Engine::new(&config)?;
-    let component = Component::from_file(&engine, wasm_path)?;
+    let engine = Engine::new(&embedding::Runtime::config())?;
+    let component = Component::from_file(&engine, &wasm_path)?;
     let cwasm = component.serialize()?;
 
     let runtime = embedding::Runtime::new()?;
     let runnable_component = runtime.load(&cwasm)?;
-    let mut running_component = runnable_component.create(
-        embedding::http::IncomingRequest {
-            method: embedding::http::Method::Get,
-            scheme: Some(embedding::http::Scheme::Https),
-            authority: Some("example.com".to_owned()),
-            path_with_query: Some("".to_owned()),
-        },
-        embedding::http::Fields::new(),
-        embedding::http::IncomingBody {},
-    )?;
+    let mut running_component = if command {
+        let arguments = std::iter::once(wasm_path).chain(args).collect();
+        runnable_component.create_command(arguments, Vec::new())?
+    } else {
+        runnable_component.create(
+            embedding::http::IncomingRequest {
+                method: embedding::http::Method::Get,
+                scheme: Some(embedding::http::Scheme::Https),
+                authority: Some("example.com".to_owned()),
+                path_with_query: Some("".to_owned()),
+            },
+            embedding::http::Fields::new(),
+            embedding::http::IncomingBody::empty(),
+        )?
+    };
 
     loop {
         let runs = running_component.step();
         println!("step ran {runs}");
         if let Some((report, res)) = running_component.check_complete() {
             println!("{report}");
-            let (response, headers) = res?;
-            println!("{response:?}");
-            println!("{headers:?}");
+            match res? {
+                embedding::Completion::Response((response, headers, body)) => {
+                    println!("{response:?}");
+                    println!("{headers:?}");
+                    println!("body ({:?}):", body.state());
+                    for (time, chunk) in body.chunks() {
+                        println!("{time:08} {:?}", String::from_utf8_lossy(&chunk));
+                    }
+                }
+                embedding::Completion::Exited { code } => {
+                    println!("exited: {code:?}");
+                }
+                embedding::Completion::OutOfFuel => {
+                    println!("ran out of fuel");
+                }
+            }
             return Ok(());
         }
-
-        if let Some(sleep_until) = running_component.earliest_deadline() {
-            println!("advance clock to {sleep_until}");
-            running_component.advance_clock(sleep_until);
-        } else {
-            println!("increment clock");
-            running_component.increment_clock();
+        match running_component.state() {
+            // A guest that yielded is ready to run again without the clock moving.
+            embedding::ComponentState::Complete | embedding::ComponentState::Runnable => {}
+            embedding::ComponentState::Sleeping { deadline } => {
+                println!("advance clock to {deadline}");
+                running_component.advance_clock(deadline);
+            }
+            embedding::ComponentState::AwaitingHost | embedding::ComponentState::Quiescent => {
+                // Nothing here pushes input or drains output, so a guest
+                // waiting on the host can never be woken either.
+                for task in running_component.blocked_tasks() {
+                    println!("{task}");
+                }
+                return Err(anyhow!("deadlocked: no task can make progress"));
+            }
         }
     }
 }